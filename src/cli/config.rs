@@ -0,0 +1,300 @@
+use super::{Metric, Opt, Output, Placeholder, Template, Threshold};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer};
+use std::collections::BTreeMap;
+use std::num::NonZeroU64;
+use std::path::{Path, PathBuf};
+use structopt::clap::ArgMatches;
+
+/// Profile used when `--profile` is not given
+const DEFAULT_PROFILE: &str = "default";
+
+#[derive(Debug)]
+pub enum ConfigError {
+    IO(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+    MissingProfile(String, Vec<String>),
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ConfigError::IO(path, e) => write!(f, "{}: {}", path.display(), e),
+            ConfigError::Parse(path, e) => write!(f, "{}: {}", path.display(), e),
+            ConfigError::MissingProfile(name, known) => write!(
+                f,
+                "No profile '{}' in the config, known profiles: {}",
+                name,
+                known.join(", ")
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    #[serde(default)]
+    profile: BTreeMap<String, Profile>,
+}
+
+/// Options of a `[profile.<name>]` table, named like the flags they stand in for.
+/// Anything given on the command line takes precedence.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Profile {
+    file_name: Option<String>,
+    state_dir: Option<PathBuf>,
+    proc_root: Option<PathBuf>,
+    sys_root: Option<PathBuf>,
+    host_root: Option<PathBuf>,
+    #[serde(deserialize_with = "parse")]
+    metric: Option<Metric>,
+    #[serde(deserialize_with = "parse")]
+    format: Option<Template>,
+    #[serde(deserialize_with = "parse")]
+    output: Option<Output>,
+    device: Option<String>,
+    #[serde(rename = "mount", alias = "mounts")]
+    mounts: Vec<String>,
+    #[serde(rename = "interface", alias = "interfaces")]
+    interfaces: Vec<String>,
+    #[serde(deserialize_with = "parse_all")]
+    warn: Vec<Threshold>,
+    #[serde(deserialize_with = "parse_all")]
+    crit: Vec<Threshold>,
+    warn_style: Option<String>,
+    crit_style: Option<String>,
+    sparkline: Option<usize>,
+    ascii: bool,
+    history: Option<usize>,
+    window: Option<u64>,
+    half_life: Option<u64>,
+    min_interval: Option<u64>,
+    #[serde(deserialize_with = "parse_all")]
+    placeholder: Vec<Placeholder>,
+    interval: Option<NonZeroU64>,
+    listen: Option<String>,
+}
+
+impl Profile {
+    /// Fills in the options of `opt` that were not given in `matches`
+    pub fn apply(self, opt: &mut Opt, matches: &ArgMatches) {
+        let unset = |arg: &str| matches.occurrences_of(arg) == 0;
+        // What to print is taken as a whole, a profile's format must not win over --metric
+        let query = opt.metrics().is_empty()
+            && opt.format.is_none()
+            && opt.output.is_none()
+            && opt.sparkline.is_none();
+        if query {
+            opt.metric = self.metric;
+            opt.format = self.format;
+            opt.output = self.output;
+            opt.sparkline = self.sparkline;
+        }
+        opt.state_dir = opt.state_dir.take().or(self.state_dir);
+        opt.device = opt.device.take().or(self.device);
+        opt.window = opt.window.or(self.window);
+        opt.half_life = opt.half_life.or(self.half_life);
+        opt.ascii |= self.ascii;
+        fill_all(&mut opt.mounts, self.mounts);
+        fill_all(&mut opt.interfaces, self.interfaces);
+        fill_all(&mut opt.warn, self.warn);
+        fill_all(&mut opt.crit, self.crit);
+        fill_all(&mut opt.placeholder, self.placeholder);
+        // These have defaults, so only the matches tell whether they were given
+        fill(&mut opt.file_name, self.file_name, unset("file-name"));
+        fill(&mut opt.proc_root, self.proc_root, unset("proc-root"));
+        fill(&mut opt.sys_root, self.sys_root, unset("sys-root"));
+        fill(&mut opt.host_root, self.host_root, unset("host-root"));
+        fill(&mut opt.warn_style, self.warn_style, unset("warn-style"));
+        fill(&mut opt.crit_style, self.crit_style, unset("crit-style"));
+        fill(&mut opt.history, self.history, unset("history"));
+        fill(
+            &mut opt.min_interval,
+            self.min_interval,
+            unset("min-interval"),
+        );
+        fill(&mut opt.interval, self.interval, unset("interval"));
+        fill(&mut opt.listen, self.listen, unset("listen"));
+    }
+}
+
+fn fill<T>(given: &mut T, configured: Option<T>, unset: bool) {
+    if let (true, Some(value)) = (unset, configured) {
+        *given = value;
+    }
+}
+
+fn fill_all<T>(given: &mut Vec<T>, configured: Vec<T>) {
+    if given.is_empty() {
+        *given = configured;
+    }
+}
+
+/// `$XDG_CONFIG_HOME/diffmetrik/config.toml`, `~/.config` if it is not set
+pub fn config_path() -> Option<PathBuf> {
+    let dir = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(dir.join("diffmetrik").join("config.toml"))
+}
+
+/// Reads the profile `name` from the config at `explicit` or the default path.
+/// Without a name the `default` profile is used if there is one, and a
+/// missing default config file is not an error.
+pub fn profile(explicit: Option<&Path>, name: Option<&str>) -> Result<Profile, ConfigError> {
+    let path = match explicit.map(Path::to_path_buf).or_else(config_path) {
+        Some(path) => path,
+        None => return Ok(Profile::default()),
+    };
+    let text = match std::fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e)
+            if e.kind() == std::io::ErrorKind::NotFound && explicit.is_none() && name.is_none() =>
+        {
+            return Ok(Profile::default())
+        }
+        Err(e) => return Err(ConfigError::IO(path, e)),
+    };
+    select(
+        parse_config(&text).map_err(|e| ConfigError::Parse(path, e))?,
+        name,
+    )
+}
+
+fn parse_config(text: &str) -> Result<Config, toml::de::Error> {
+    toml::from_str(text)
+}
+
+fn select(mut config: Config, name: Option<&str>) -> Result<Profile, ConfigError> {
+    match (config.profile.remove(name.unwrap_or(DEFAULT_PROFILE)), name) {
+        (Some(profile), _) => Ok(profile),
+        (None, None) => Ok(Profile::default()),
+        (None, Some(name)) => Err(ConfigError::MissingProfile(
+            name.to_string(),
+            config.profile.keys().cloned().collect(),
+        )),
+    }
+}
+
+/// Values are written the same way as on the command line
+fn parse<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: std::str::FromStr<Err = String>,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|s| s.parse().map_err(D::Error::custom))
+        .transpose()
+}
+
+fn parse_all<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: std::str::FromStr<Err = String>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|s| s.parse().map_err(D::Error::custom))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use structopt::StructOpt;
+
+    static CONFIG: &str = r#"
+        [profile.default]
+        metric = "cpu"
+
+        [profile.laptop]
+        format = "D:{download} U:{upload}"
+        interface = ["wl*"]
+        warn = ["download=50M"]
+        warn-style = "fg=colour214"
+        window = 10
+        history = 20
+
+        [profile.server]
+        metric = "disk-usage"
+        # The plural is accepted as well
+        mounts = ["/", "/var"]
+    "#;
+
+    fn opt(args: &[&str], profile: Option<&str>) -> Opt {
+        let matches =
+            Opt::clap().get_matches_from(std::iter::once("diffmetrik").chain(args.iter().copied()));
+        let mut opt = Opt::from_clap(&matches);
+        let config = parse_config(CONFIG).unwrap();
+        select(config, profile).unwrap().apply(&mut opt, &matches);
+        opt
+    }
+
+    #[test]
+    fn test_profile() {
+        let laptop = opt(&[], Some("laptop"));
+        assert_eq!(
+            laptop.format,
+            Some("D:{download} U:{upload}".parse().unwrap())
+        );
+        assert_eq!(laptop.metric, None);
+        assert_eq!(laptop.interfaces, vec!["wl*"]);
+        assert_eq!(laptop.warn, vec!["download=50M".parse().unwrap()]);
+        assert_eq!(laptop.warn_style, "fg=colour214");
+        assert_eq!(laptop.crit_style, "fg=red");
+        assert_eq!(laptop.window, Some(10));
+        assert_eq!(laptop.history, 20);
+
+        let server = opt(&[], Some("server"));
+        assert_eq!(server.metric, Some("disk-usage".parse().unwrap()));
+        assert_eq!(server.mounts, vec!["/", "/var"]);
+
+        assert_eq!(opt(&[], None).metric, Some("cpu".parse().unwrap()));
+        assert_eq!(
+            opt(&["--metric", "memory"], None).metric,
+            Some("memory".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_flags_override_profile() {
+        let laptop = opt(
+            &[
+                "--metric",
+                "download",
+                "--interface",
+                "eth0",
+                "--warn-style",
+                "fg=yellow",
+                "--history",
+                "5",
+            ],
+            Some("laptop"),
+        );
+        assert_eq!(laptop.metric, Some("download".parse().unwrap()));
+        assert_eq!(laptop.format, None, "a profile's query is taken as a whole");
+        assert_eq!(laptop.interfaces, vec!["eth0"]);
+        assert_eq!(laptop.warn_style, "fg=yellow");
+        assert_eq!(laptop.history, 5);
+        assert_eq!(laptop.window, Some(10));
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        assert!(parse_config("[profile.a]\nmetirc = \"cpu\"").is_err());
+        assert!(parse_config("[profile.a]\nmetric = \"bogus\"").is_err());
+        assert!(parse_config("[profile.a]\nwarn = [\"cpu\"]").is_err());
+        assert!(parse_config("[profile.a]\ninterval = 0").is_err());
+        let config = parse_config(CONFIG).unwrap();
+        match select(config, Some("desktop")) {
+            Err(ConfigError::MissingProfile(name, known)) => {
+                assert_eq!(name, "desktop");
+                assert_eq!(known, vec!["default", "laptop", "server"]);
+            }
+            other => panic!("expected a missing profile, got {:?}", other),
+        }
+        assert!(select(Config::default(), None).is_ok());
+    }
+}
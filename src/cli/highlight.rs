@@ -0,0 +1,207 @@
+use super::metric::Metric;
+use serde::{Deserialize, Serialize};
+
+/// Limit a metric is compared against
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Limit {
+    Value(f64),
+    /// Multiple of the number of CPU cores, e.g. for the load average
+    Cores(f64),
+}
+
+/// Limit of a single metric, e.g. `download=50M` or `cpu=cores`
+///
+/// Values take the scale suffixes of the metric's unit, `k`, `M`, `G` and `T`,
+/// or end with `cores` to be multiplied by the number of CPU cores.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Threshold {
+    source: String,
+    metric: Metric,
+    limit: Limit,
+}
+
+impl Threshold {
+    fn exceeded(&self, value: f64, cores: usize) -> bool {
+        let limit = match self.limit {
+            Limit::Value(limit) => limit,
+            Limit::Cores(factor) => factor * cores.max(1) as f64,
+        };
+        value > limit
+    }
+}
+
+fn parse_limit(metric: Metric, s: &str) -> Result<Limit, String> {
+    let number = |n: &str| {
+        n.parse::<f64>()
+            .map_err(|_| format!("invalid threshold '{}' for {}", s, metric))
+    };
+    if let Some(factor) = s.strip_suffix("cores") {
+        return match factor {
+            "" => Ok(Limit::Cores(1.0)),
+            factor => Ok(Limit::Cores(number(factor)?)),
+        };
+    }
+    let s = s.strip_suffix('%').unwrap_or(s);
+    match s.chars().last().filter(char::is_ascii_alphabetic) {
+        Some(scale) => {
+            let factor = metric
+                .unit()
+                .scale_factor(scale)
+                .ok_or_else(|| format!("scale '{}' does not apply to {}", scale, metric))?;
+            Ok(Limit::Value(number(&s[..s.len() - 1])? * factor))
+        }
+        None => Ok(Limit::Value(number(s)?)),
+    }
+}
+
+impl std::str::FromStr for Threshold {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Threshold, String> {
+        let (metric, limit) = s
+            .split_once('=')
+            .ok_or_else(|| format!("threshold '{}' must be <metric>=<value>", s))?;
+        let metric = metric
+            .parse::<Metric>()
+            .map_err(|e| format!("unknown metric '{}' in threshold, {}", metric, e))?;
+        Ok(Threshold {
+            source: s.to_string(),
+            metric,
+            limit: parse_limit(metric, limit)?,
+        })
+    }
+}
+
+impl std::convert::TryFrom<String> for Threshold {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Threshold, String> {
+        s.parse()
+    }
+}
+
+impl From<Threshold> for String {
+    fn from(threshold: Threshold) -> String {
+        threshold.source
+    }
+}
+
+fn default_warn_style() -> String {
+    "fg=yellow".to_string()
+}
+
+fn default_crit_style() -> String {
+    "fg=red".to_string()
+}
+
+/// Wraps values that cross a threshold in tmux style sequences
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Highlight {
+    #[serde(default)]
+    pub warn: Vec<Threshold>,
+    #[serde(default)]
+    pub crit: Vec<Threshold>,
+    #[serde(default = "default_warn_style")]
+    pub warn_style: String,
+    #[serde(default = "default_crit_style")]
+    pub crit_style: String,
+}
+
+impl Default for Highlight {
+    fn default() -> Highlight {
+        Highlight {
+            warn: Vec::new(),
+            crit: Vec::new(),
+            warn_style: default_warn_style(),
+            crit_style: default_crit_style(),
+        }
+    }
+}
+
+impl Highlight {
+    /// Style of the most severe threshold `value` of `metric` crosses
+    fn style(&self, metric: &Metric, value: f64, cores: usize) -> Option<&str> {
+        let crossed = |thresholds: &[Threshold]| {
+            thresholds
+                .iter()
+                .any(|t| t.metric == *metric && t.exceeded(value, cores))
+        };
+        if crossed(&self.crit) {
+            Some(&self.crit_style)
+        } else if crossed(&self.warn) {
+            Some(&self.warn_style)
+        } else {
+            None
+        }
+    }
+
+    pub fn apply(&self, metric: &Metric, value: f64, cores: usize, text: String) -> String {
+        match self.style(metric, value, cores) {
+            Some(style) => format!("#[{}]{}#[{}]", style, text, reset(style)),
+            None => text,
+        }
+    }
+}
+
+/// Undoes a tmux style, `fg=red,bold` becomes `fg=default,nobold`, so the
+/// surrounding status line style is kept
+fn reset(style: &str) -> String {
+    style
+        .split([',', ' '])
+        .filter(|attr| !attr.is_empty())
+        .map(|attr| match attr.split_once('=') {
+            Some((key, _)) => format!("{}=default", key),
+            None => match attr.strip_prefix("no") {
+                Some(attr) => attr.to_string(),
+                None => format!("no{}", attr),
+            },
+        })
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_threshold() {
+        let t: Threshold = "download=50M".parse().unwrap();
+        assert_eq!(t.limit, Limit::Value(50.0 * 1024.0 * 1024.0));
+        let t: Threshold = "cpu=1.5cores".parse().unwrap();
+        assert_eq!(t.limit, Limit::Cores(1.5));
+        let t: Threshold = "cpu-usage=90%".parse().unwrap();
+        assert_eq!(t.limit, Limit::Value(90.0));
+        assert!("download".parse::<Threshold>().is_err());
+        assert!("bogus=1".parse::<Threshold>().is_err());
+        assert!("cpu-usage=9k".parse::<Threshold>().is_err());
+        assert!("download=fast".parse::<Threshold>().is_err());
+    }
+
+    #[test]
+    fn apply_styles() {
+        let highlight = Highlight {
+            warn: vec!["cpu=0.5cores".parse().unwrap()],
+            crit: vec!["cpu=cores".parse().unwrap()],
+            ..Highlight::default()
+        };
+        let apply = |metric, value| highlight.apply(&metric, value, 4, "x".to_string());
+        assert_eq!(apply("cpu".parse().unwrap(), 1.0), "x");
+        assert_eq!(
+            apply("cpu".parse().unwrap(), 3.0),
+            "#[fg=yellow]x#[fg=default]"
+        );
+        assert_eq!(
+            apply("cpu".parse().unwrap(), 5.0),
+            "#[fg=red]x#[fg=default]"
+        );
+        assert_eq!(apply("download".parse().unwrap(), 5.0), "x");
+    }
+
+    #[test]
+    fn reset_style() {
+        assert_eq!(reset("fg=red,bold"), "fg=default,nobold");
+        assert_eq!(reset("bg=colour1 noitalics"), "bg=default,italics");
+    }
+}
@@ -0,0 +1,146 @@
+use super::metric::{Metric, Selection};
+use diffmetrik::metrics::format::Unit;
+use diffmetrik::metrics::MetricRate;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Every metric as a raw number with its unit, alongside the rates it was
+/// computed from. Rates and metrics of sources that failed to collect are null.
+#[derive(Serialize)]
+struct Report {
+    #[serde(flatten)]
+    rate: serde_json::Map<String, serde_json::Value>,
+    metrics: BTreeMap<&'static str, Option<Value>>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(untagged)]
+enum Value {
+    Single {
+        unit: Unit,
+        value: f64,
+    },
+    /// Keyed by core index or mount point
+    Multiple {
+        unit: Unit,
+        values: BTreeMap<String, f64>,
+    },
+}
+
+fn value(metric: &Metric, rate: &MetricRate, selection: &Selection) -> Option<Value> {
+    if !metric.available(rate) {
+        return None;
+    }
+    let values = metric.values(rate, selection, None);
+    let unit = metric.unit();
+    if !metric.multiple() {
+        return Some(Value::Single {
+            unit,
+            value: values.first().map_or(0.0, |(_, v)| *v),
+        });
+    }
+    // Cores are not labelled, their index is
+    Some(Value::Multiple {
+        unit,
+        values: values
+            .into_iter()
+            .enumerate()
+            .map(|(i, (label, v))| (label.unwrap_or_else(|| i.to_string()), v))
+            .collect(),
+    })
+}
+
+/// Renders `rate` as a single line of JSON, limited to `metric` if given
+pub fn render(metric: Option<&Metric>, rate: &MetricRate, selection: &Selection) -> String {
+    let metrics = Metric::all()
+        .filter(|m| metric.is_none_or(|metric| metric == m))
+        .map(|m| (m.name(), value(&m, rate, selection)))
+        .collect();
+    let mut rates = match serde_json::to_value(rate) {
+        Ok(serde_json::Value::Object(rates)) => rates,
+        _ => unreachable!("metric rates are always serializable to an object"),
+    };
+    for source in &rate.failed {
+        rates.insert(source.clone(), serde_json::Value::Null);
+    }
+    let report = Report {
+        rate: rates,
+        metrics,
+    };
+    serde_json::to_string(&report).expect("metric rates are always serializable")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diffmetrik::metrics::{CPUMetrics, FilesystemUsage};
+    use std::time::Duration;
+
+    fn rate() -> MetricRate {
+        MetricRate {
+            time: Duration::new(1_600_000_000, 500_000_000),
+            window: Duration::new(4, 0),
+            cpu: CPUMetrics {
+                m1: 1.5,
+                m5: 0.5,
+                m15: 0.25,
+            },
+            filesystems: vec![FilesystemUsage {
+                mount_point: "/".to_string(),
+                total: 1000,
+                free: 500,
+                available: 500,
+            }],
+            ..MetricRate::default()
+        }
+    }
+
+    #[test]
+    fn render_single_metric() {
+        let json = render(
+            Some(&"cpu".parse().unwrap()),
+            &rate(),
+            &Selection::default(),
+        );
+        let parsed: serde_json::Value = serde_json::from_str(&json).expect("valid json");
+        assert_eq!(parsed["time"], 1_600_000_000.5);
+        assert_eq!(parsed["window"], 4.0);
+        assert_eq!(parsed["cpu"]["m1"], 1.5);
+        assert_eq!(
+            parsed["metrics"],
+            serde_json::json!({"cpu": {"unit": "number", "value": 1.5}})
+        );
+    }
+
+    #[test]
+    fn render_all_metrics() {
+        let json = render(None, &rate(), &Selection::default());
+        let parsed: serde_json::Value = serde_json::from_str(&json).expect("valid json");
+        let metrics = parsed["metrics"].as_object().expect("metrics object");
+        assert_eq!(metrics.len(), Metric::names().len());
+        assert_eq!(
+            metrics["download"],
+            serde_json::json!({"unit": "bytes_per_second", "value": 0.0})
+        );
+        assert_eq!(
+            metrics["disk-used"],
+            serde_json::json!({"unit": "bytes", "values": {"/": 500.0}})
+        );
+        assert_eq!(parsed["filesystems"][0]["mount_point"], "/");
+    }
+
+    #[test]
+    fn render_failed_source() {
+        let rate = MetricRate {
+            failed: vec!["memory".to_string()],
+            ..rate()
+        };
+        let json = render(None, &rate, &Selection::default());
+        let parsed: serde_json::Value = serde_json::from_str(&json).expect("valid json");
+        assert_eq!(parsed["memory"], serde_json::Value::Null);
+        assert_eq!(parsed["failed"], serde_json::json!(["memory"]));
+        assert_eq!(parsed["metrics"]["memory"], serde_json::Value::Null);
+        assert_eq!(parsed["metrics"]["swap"], serde_json::Value::Null);
+        assert_eq!(parsed["cpu"]["m1"], 1.5);
+    }
+}
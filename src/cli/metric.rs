@@ -0,0 +1,168 @@
+use super::highlight::Highlight;
+use super::placeholder::{placeholder, Placeholder, Unavailable};
+use diffmetrik::metrics::format::{format_value, Spec, Unit};
+use diffmetrik::metrics::{metric_defs, MetricDef, MetricRate, Values};
+use serde::{Deserialize, Serialize};
+
+pub use diffmetrik::metrics::Selection;
+
+/// A metric of one of the sources registered in `metrics`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Metric(&'static MetricDef);
+
+impl Metric {
+    /// Names of all metrics
+    pub fn names() -> Vec<&'static str> {
+        metric_defs().map(|def| def.name).collect()
+    }
+
+    pub fn all() -> impl Iterator<Item = Metric> {
+        metric_defs().map(Metric)
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.0.name
+    }
+}
+
+impl std::str::FromStr for Metric {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Metric, String> {
+        let s = s.to_ascii_lowercase();
+        Metric::all()
+            .find(|m| m.name() == s)
+            .ok_or_else(|| format!("valid values: {}", Metric::names().join(", ")))
+    }
+}
+
+impl std::convert::TryFrom<String> for Metric {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Metric, String> {
+        s.parse()
+    }
+}
+
+impl From<Metric> for String {
+    fn from(metric: Metric) -> String {
+        metric.name().to_string()
+    }
+}
+
+impl std::fmt::Display for Metric {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl Metric {
+    /// Prefix printed in front of the value when rendered on its own
+    pub fn label(&self) -> Option<&'static str> {
+        self.0.label
+    }
+
+    pub fn unit(&self) -> Unit {
+        self.0.unit
+    }
+
+    /// Reports a value per core, device or mount point
+    pub fn multiple(&self) -> bool {
+        self.0.multiple
+    }
+
+    /// Validates the part after the dot in a `{metric.field}` placeholder
+    ///
+    /// The field picks the load average window for `cpu`, the core index for
+    /// `cpu-cores`, and overrides the selection of the network, disk and
+    /// filesystem metrics.
+    pub fn check_field(&self, field: &str) -> Result<(), String> {
+        match self.0.check_field {
+            Some(check) => check(field),
+            None => Err(format!("{} does not take a field", self)),
+        }
+    }
+
+    /// Current values of the metric, labelled if there can be more than one
+    pub fn values(&self, rate: &MetricRate, selection: &Selection, field: Option<&str>) -> Values {
+        (self.0.values)(rate, selection, field)
+    }
+
+    /// Whether the source of the metric was collected for `rate`
+    pub fn available(&self, rate: &MetricRate) -> bool {
+        rate.available(self.0)
+    }
+
+    /// Values formatted with `spec`, highlighted and joined by spaces, or the
+    /// collect placeholder if the source failed or has nothing selected, e.g.
+    /// a `--mount` that does not exist
+    pub fn format(
+        &self,
+        rate: &MetricRate,
+        selection: &Selection,
+        highlight: &Highlight,
+        placeholders: &[Placeholder],
+        field: Option<&str>,
+        spec: &Spec,
+    ) -> String {
+        let values = self.values(rate, selection, field);
+        if !self.available(rate) || values.is_empty() {
+            return placeholder(placeholders, Unavailable::Collect).to_string();
+        }
+        let cores = rate.cpu_utilization.cores.len();
+        values
+            .iter()
+            .map(|(label, value)| {
+                let text = format_value(*value, self.unit(), spec);
+                let value = highlight.apply(self, *value, cores, text);
+                match label {
+                    Some(label) => format!("{}: {}", label, value),
+                    None => value,
+                }
+            })
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+}
+
+pub fn render(
+    metric: &Metric,
+    rate: &MetricRate,
+    selection: &Selection,
+    highlight: &Highlight,
+    placeholders: &[Placeholder],
+) -> String {
+    let values = metric.format(
+        rate,
+        selection,
+        highlight,
+        placeholders,
+        None,
+        &Spec::default(),
+    );
+    match metric.label() {
+        Some(label) => format!("{}: {}", label, values),
+        None => values,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_round_trip() {
+        for name in Metric::names() {
+            let metric: Metric = name.parse().expect("known metric");
+            assert_eq!(metric.name(), name);
+        }
+    }
+
+    #[test]
+    fn parse_is_case_insensitive() {
+        let download = "Download".parse::<Metric>().expect("known metric");
+        assert_eq!(download.name(), "download");
+        assert!("eth0".parse::<Metric>().is_err());
+    }
+}
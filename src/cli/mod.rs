@@ -1,35 +1,450 @@
-use structopt::clap::arg_enum;
+use diffmetrik::metrics::{Averaging, Metrics, RateError, Roots};
+use serde::{Deserialize, Serialize};
+use std::num::NonZeroU64;
+use std::time::Duration;
+use structopt::clap::{Error, ErrorKind};
 use structopt::StructOpt;
 
-arg_enum! {
-    #[derive(Debug)]
-    pub enum Metric {
-        Cpu,
-        Download,
-        Upload,
+mod config;
+mod highlight;
+mod json;
+mod metric;
+mod placeholder;
+mod sparkline;
+mod template;
+
+pub use config::config_path;
+pub use highlight::{Highlight, Threshold};
+pub use metric::{render, Metric, Selection};
+pub use placeholder::{placeholder, Placeholder, Unavailable};
+pub use sparkline::Sparkline;
+pub use template::Template;
+
+/// What to print, a single metric, several of them side by side, a template
+/// combining them, the raw rates as JSON, optionally limited to one metric,
+/// the history of a metric as a sparkline, or every rate of it line by line
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Query {
+    Metric(Metric),
+    Metrics(Vec<Metric>),
+    Format(Template),
+    Json(Option<Metric>),
+    Sparkline(Sparkline),
+    History(Metric),
+}
+
+impl Query {
+    /// Renders the newest rate of `metrics`, or all of them for sparklines and history
+    pub fn render(
+        &self,
+        metrics: &Metrics,
+        selection: &Selection,
+        highlight: &Highlight,
+        averaging: &Averaging,
+        placeholders: &[Placeholder],
+    ) -> Result<String, RateError> {
+        let all_rates = || {
+            let rates = metrics.rates();
+            if rates.is_empty() {
+                return Err(RateError::NotEnoughData);
+            }
+            Ok(rates)
+        };
+        let rendered = match self {
+            Query::Sparkline(sparkline) => sparkline.render(&all_rates()?, selection),
+            Query::History(metric) => all_rates()?
+                .iter()
+                .map(|rate| {
+                    let value = render(metric, rate, selection, highlight, placeholders);
+                    format!("{} {}", rate.time.as_secs(), value)
+                })
+                .collect::<Vec<String>>()
+                .join("\n"),
+            Query::Metric(metric) => render(
+                metric,
+                &metrics.rate(averaging)?,
+                selection,
+                highlight,
+                placeholders,
+            ),
+            Query::Metrics(list) => {
+                let rate = metrics.rate(averaging)?;
+                list.iter()
+                    .map(|metric| render(metric, &rate, selection, highlight, placeholders))
+                    .collect::<Vec<String>>()
+                    .join(" ")
+            }
+            Query::Format(template) => template.render(
+                &metrics.rate(averaging)?,
+                selection,
+                highlight,
+                placeholders,
+            ),
+            Query::Json(metric) => {
+                json::render(metric.as_ref(), &metrics.rate(averaging)?, selection)
+            }
+        };
+        Ok(rendered)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Output {
+    Text,
+    Json,
+}
+
+impl Output {
+    pub const VARIANTS: &'static [&'static str] = &["text", "json"];
+}
+
+impl std::str::FromStr for Output {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Output, String> {
+        match s {
+            "text" => Ok(Output::Text),
+            "json" => Ok(Output::Json),
+            _ => Err(format!("valid values: {}", Output::VARIANTS.join(", "))),
+        }
     }
 }
 
 #[derive(StructOpt, Debug)]
-#[structopt(name = "baisc")]
+#[structopt(name = "diffmetrik")]
 pub struct Opt {
-    #[structopt(long, default_value = "diffmetrik.json")]
+    #[structopt(subcommand)]
+    pub command: Option<Command>,
+
+    #[structopt(long, default_value = "diffmetrik.json", global = true)]
     pub file_name: String,
 
-    #[structopt(short, long, possible_values = &Metric::variants(), case_insensitive = true)]
-    pub metric: Metric,
+    /// Directory for the history and the daemon socket, defaults to
+    /// $XDG_RUNTIME_DIR/diffmetrik or a per-user directory in the temp dir
+    #[structopt(long, parse(from_os_str), global = true)]
+    pub state_dir: Option<std::path::PathBuf>,
+
+    /// Config file with the profiles, defaults to ~/.config/diffmetrik/config.toml
+    #[structopt(long, parse(from_os_str), global = true)]
+    pub config: Option<std::path::PathBuf>,
+
+    /// Profile of the config file to take options from, flags given here
+    /// override it. Defaults to the profile named "default" if there is one
+    #[structopt(long, global = true)]
+    pub profile: Option<String>,
+
+    /// Where procfs is mounted, e.g. /host/proc to monitor the host from a container
+    #[structopt(long, default_value = "/proc", parse(from_os_str), global = true)]
+    pub proc_root: std::path::PathBuf,
+
+    /// Where sysfs is mounted, e.g. /host/sys to monitor the host from a container
+    #[structopt(long, default_value = "/sys", parse(from_os_str), global = true)]
+    pub sys_root: std::path::PathBuf,
+
+    /// Where the root filesystem of the machine of `--proc-root` is mounted,
+    /// e.g. /host. Filesystem capacity is measured below it
+    #[structopt(long, default_value = "/", parse(from_os_str), global = true)]
+    pub host_root: std::path::PathBuf,
+
+    /// Metric to print, same as `get <metric>`
+    #[structopt(
+        short,
+        long,
+        global = true,
+        possible_values = &Metric::names(),
+        case_insensitive = true
+    )]
+    pub metric: Option<Metric>,
+
+    /// Template rendering several metrics at once, e.g. "D:{download} U:{upload} C:{cpu.m1:.1}".
+    /// Placeholders take a format spec after a colon: [align][width][.precision][B|k|M|G|T]
+    #[structopt(short, long, conflicts_with = "metric", global = true)]
+    pub format: Option<Template>,
+
+    /// Output format, defaults to text. json prints the raw rates and every
+    /// metric with its unit, or only `--metric` if given
+    #[structopt(long, possible_values = Output::VARIANTS, conflicts_with = "format", global = true)]
+    pub output: Option<Output>,
+
+    /// Block device for the disk metrics, defaults to the sum of all physical disks
+    #[structopt(long, global = true)]
+    pub device: Option<String>,
+
+    /// Mount point for the filesystem capacity metrics, measured whatever its
+    /// type instead of the local filesystems. Can be repeated
+    #[structopt(long = "mount", global = true)]
+    pub mounts: Vec<String>,
+
+    /// Network interface glob, prefix with ! to exclude, can be repeated.
+    /// Defaults to all physical interfaces
+    #[structopt(long = "interface", global = true)]
+    pub interfaces: Vec<String>,
+
+    /// Warning threshold as <metric>=<value>, e.g. download=50M or cpu=cores,
+    /// values above it are wrapped in `--warn-style`. Can be repeated
+    #[structopt(long, global = true)]
+    pub warn: Vec<Threshold>,
+
+    /// Critical threshold, same as `--warn` but styled with `--crit-style`
+    #[structopt(long, global = true)]
+    pub crit: Vec<Threshold>,
+
+    /// tmux style of values above a warning threshold
+    #[structopt(long, default_value = "fg=yellow", global = true)]
+    pub warn_style: String,
+
+    /// tmux style of values above a critical threshold
+    #[structopt(long, default_value = "fg=red", global = true)]
+    pub crit_style: String,
+
+    /// Print the last N rates of the metric as a sparkline
+    #[structopt(long, conflicts_with_all = &["format", "output"], global = true)]
+    pub sparkline: Option<usize>,
+
+    /// Draw sparklines with ASCII characters instead of Unicode blocks
+    #[structopt(long, global = true)]
+    pub ascii: bool,
+
+    /// Number of samples to keep, sparklines can show one less than that
+    #[structopt(long, default_value = "3", global = true)]
+    pub history: usize,
+
+    /// Compute rates over at least this many seconds, 0 for the two newest
+    /// samples. Defaults to the three newest samples
+    #[structopt(long, global = true)]
+    pub window: Option<u64>,
+
+    /// Smooth the network and disk rates with an exponentially weighted moving
+    /// average, a sample this many seconds old weighs half as much as a new one
+    #[structopt(long, global = true)]
+    pub half_life: Option<u64>,
+
+    /// Minimum number of seconds between samples written to the storage file
+    /// by one-shot calls
+    #[structopt(long, default_value = "2", global = true)]
+    pub min_interval: u64,
 
+    /// Text printed when there is no value, [kind=]text where kind is one of
+    /// not-enough-data, window-too-short, clock-skew, storage-error or
+    /// collect-error. Without a kind it applies to all of them. Can be repeated
+    #[structopt(long, global = true)]
+    pub placeholder: Vec<Placeholder>,
+
+    /// Same as the daemon subcommand
     #[structopt(long)]
     pub daemon: bool,
 
-    #[structopt(short, long)]
+    /// Seconds between the samples of the daemon and the lines printed by watch
+    #[structopt(long, default_value = "2", global = true)]
+    pub interval: NonZeroU64,
+
+    /// Same as the serve subcommand
+    #[structopt(long, conflicts_with = "daemon")]
+    pub serve: bool,
+
+    /// Address the Prometheus exporter listens on
+    #[structopt(long, default_value = "127.0.0.1:9184", global = true)]
+    pub listen: String,
+
+    #[structopt(short, long, global = true)]
     pub debug: bool,
 }
 
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+pub enum Command {
+    /// Print the current value of one or more metrics, or of `--format`
+    Get {
+        #[structopt(possible_values = &Metric::names(), case_insensitive = true)]
+        metrics: Vec<Metric>,
+    },
+    /// Print the current value every `--interval` seconds until interrupted
+    Watch {
+        #[structopt(possible_values = &Metric::names(), case_insensitive = true)]
+        metrics: Vec<Metric>,
+    },
+    /// Run in the background, sampling the metrics every `--interval` seconds
+    Daemon,
+    /// Serve the metrics over HTTP for Prometheus on `--listen`
+    Serve,
+    /// Print every rate of a metric in the history, oldest first
+    History {
+        // The global --metric already goes by the name "metric"
+        #[structopt(
+            name = "METRIC",
+            possible_values = &Metric::names(),
+            case_insensitive = true
+        )]
+        metric: Metric,
+    },
+    /// List the available metrics
+    List,
+    /// Clear the stored history
+    Reset,
+    /// Check that the metrics can be collected and stored
+    Doctor,
+}
+
+impl Opt {
+    /// The subcommand, or the one the flags stand for without it
+    pub fn command(&self) -> Command {
+        match &self.command {
+            Some(command) => command.clone(),
+            None if self.daemon => Command::Daemon,
+            None if self.serve => Command::Serve,
+            None => Command::Get {
+                metrics: Vec::new(),
+            },
+        }
+    }
+
+    /// Metrics named by get or watch, or by `--metric`
+    pub fn metrics(&self) -> Vec<Metric> {
+        match &self.command {
+            Some(Command::Get { metrics }) | Some(Command::Watch { metrics })
+                if !metrics.is_empty() =>
+            {
+                metrics.clone()
+            }
+            _ => self.metric.into_iter().collect(),
+        }
+    }
+
+    pub fn query(&self) -> Option<Query> {
+        let metrics = self.metrics();
+        match (
+            metrics.as_slice(),
+            &self.format,
+            self.output,
+            self.sparkline,
+        ) {
+            ([metric], _, _, Some(length)) => Some(Query::Sparkline(Sparkline {
+                metric: *metric,
+                length,
+                ascii: self.ascii,
+            })),
+            ([metric], _, Some(Output::Json), _) => Some(Query::Json(Some(*metric))),
+            (_, _, Some(Output::Json), _) => Some(Query::Json(None)),
+            (_, Some(template), _, _) => Some(Query::Format(template.clone())),
+            ([], None, _, _) => None,
+            ([metric], None, _, _) => Some(Query::Metric(*metric)),
+            (_, None, _, _) => Some(Query::Metrics(metrics)),
+        }
+    }
+
+    /// Time between the samples of the daemon and the lines printed by watch
+    pub fn interval(&self) -> Duration {
+        Duration::new(self.interval.get(), 0)
+    }
+
+    /// Samples to keep, enough for the requested sparkline
+    pub fn history(&self) -> usize {
+        let sparkline = self.sparkline.map_or(0, |length| length + 1);
+        self.history.max(sparkline).max(2)
+    }
+
+    pub fn averaging(&self) -> Averaging {
+        Averaging {
+            window: self.window,
+            half_life: self.half_life,
+        }
+    }
+
+    pub fn highlight(&self) -> Highlight {
+        Highlight {
+            warn: self.warn.clone(),
+            crit: self.crit.clone(),
+            warn_style: self.warn_style.clone(),
+            crit_style: self.crit_style.clone(),
+        }
+    }
+
+    pub fn selection(&self) -> Selection {
+        Selection {
+            device: self.device.clone(),
+            mounts: self.mounts.clone(),
+            interfaces: self.interfaces.clone(),
+        }
+    }
+
+    pub fn roots(&self) -> Roots {
+        Roots {
+            proc: self.proc_root.clone(),
+            sys: self.sys_root.clone(),
+            host: self.host_root.clone(),
+        }
+    }
+}
+
 pub fn opt_from_args() -> Opt {
-    let opt = Opt::from_args();
+    let matches = Opt::clap().get_matches();
+    let mut opt = Opt::from_clap(&matches);
+    match config::profile(opt.config.as_deref(), opt.profile.as_deref()) {
+        Ok(profile) => profile.apply(&mut opt, &matches),
+        Err(e) => Error::with_description(&e.to_string(), ErrorKind::InvalidValue).exit(),
+    }
+    let command = opt.command();
+    let renders = matches!(command, Command::Get { .. } | Command::Watch { .. });
+    if renders && opt.query().is_none() {
+        Error::with_description(
+            "A metric or --format is required, e.g. `diffmetrik get cpu`",
+            ErrorKind::MissingRequiredArgument,
+        )
+        .exit();
+    }
     if opt.debug {
         dbg!(&opt);
     }
     opt
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Opt {
+        Opt::from_iter(std::iter::once("diffmetrik").chain(args.iter().copied()))
+    }
+
+    #[test]
+    fn test_commands() {
+        let cpu: Metric = "cpu".parse().unwrap();
+        let download: Metric = "download".parse().unwrap();
+        assert_eq!(parse(&["get", "cpu"]).query(), Some(Query::Metric(cpu)));
+        assert_eq!(
+            parse(&["--metric", "cpu"]).query(),
+            Some(Query::Metric(cpu))
+        );
+        assert_eq!(
+            parse(&["get", "download", "cpu", "--interface", "eth0"]).query(),
+            Some(Query::Metrics(vec![download, cpu]))
+        );
+        assert_eq!(
+            parse(&["get", "cpu", "--output", "json"]).query(),
+            Some(Query::Json(Some(cpu)))
+        );
+        let sparkline = parse(&["watch", "cpu", "--sparkline", "5"]);
+        assert!(matches!(sparkline.command(), Command::Watch { .. }));
+        assert!(matches!(sparkline.query(), Some(Query::Sparkline(_))));
+        assert_eq!(parse(&["get"]).query(), None);
+
+        assert_eq!(parse(&["--daemon"]).command(), Command::Daemon);
+        assert_eq!(
+            parse(&["daemon", "--interval", "5"]).interval(),
+            Duration::new(5, 0)
+        );
+        assert!(Opt::from_iter_safe(&["diffmetrik", "daemon", "--interval", "0"]).is_err());
+        assert_eq!(parse(&["--serve"]).command(), Command::Serve);
+        assert_eq!(
+            parse(&["history", "cpu"]).command(),
+            Command::History { metric: cpu }
+        );
+        assert_eq!(
+            parse(&["--format", "{cpu}"]).command(),
+            Command::Get {
+                metrics: Vec::new()
+            }
+        );
+        assert!(Opt::from_iter_safe(&["diffmetrik", "get", "bogus"]).is_err());
+    }
+}
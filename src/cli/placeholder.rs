@@ -0,0 +1,141 @@
+use diffmetrik::metrics::RateError;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize};
+
+/// Why there is no value to print
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Unavailable {
+    Rate(RateError),
+    /// The history could not be read or written
+    Storage,
+    /// The source of the metric could not be sampled, e.g. procfs is not
+    /// where `--proc-root` says
+    Collect,
+}
+
+impl Unavailable {
+    pub const KINDS: &'static [&'static str] = &[
+        "not-enough-data",
+        "window-too-short",
+        "clock-skew",
+        "storage-error",
+        "collect-error",
+    ];
+
+    fn kind(&self) -> &'static str {
+        match self {
+            Unavailable::Rate(RateError::NotEnoughData) => "not-enough-data",
+            Unavailable::Rate(RateError::WindowTooShort) => "window-too-short",
+            Unavailable::Rate(RateError::ClockSkew) => "clock-skew",
+            Unavailable::Storage => "storage-error",
+            Unavailable::Collect => "collect-error",
+        }
+    }
+
+    fn default_text(&self) -> &'static str {
+        match self {
+            // Both go away by waiting for the next sample
+            Unavailable::Rate(RateError::NotEnoughData)
+            | Unavailable::Rate(RateError::WindowTooShort) => "Not enough data",
+            Unavailable::Rate(RateError::ClockSkew) => "Clock skew",
+            Unavailable::Storage => "Storage error",
+            Unavailable::Collect => "Collect error",
+        }
+    }
+}
+
+impl From<RateError> for Unavailable {
+    fn from(e: RateError) -> Unavailable {
+        Unavailable::Rate(e)
+    }
+}
+
+/// Text printed instead of a value, `[kind=]text`, without a kind it applies to all of them
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(into = "String")]
+pub struct Placeholder {
+    kind: Option<&'static str>,
+    text: String,
+}
+
+impl std::str::FromStr for Placeholder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Placeholder, String> {
+        let kind = s.split_once('=').and_then(|(kind, text)| {
+            let kind = Unavailable::KINDS.iter().find(|k| **k == kind)?;
+            Some((*kind, text))
+        });
+        Ok(match kind {
+            Some((kind, text)) => Placeholder {
+                kind: Some(kind),
+                text: text.to_string(),
+            },
+            None => Placeholder {
+                kind: None,
+                text: s.to_string(),
+            },
+        })
+    }
+}
+
+// Derived it would borrow the kind from the input, it is one of `KINDS` instead
+impl<'de> Deserialize<'de> for Placeholder {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Placeholder, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(D::Error::custom)
+    }
+}
+
+impl From<Placeholder> for String {
+    fn from(placeholder: Placeholder) -> String {
+        match placeholder.kind {
+            Some(kind) => format!("{}={}", kind, placeholder.text),
+            None => placeholder.text,
+        }
+    }
+}
+
+/// Text for `unavailable`, the last matching placeholder wins
+pub fn placeholder(placeholders: &[Placeholder], unavailable: Unavailable) -> &str {
+    placeholders
+        .iter()
+        .rev()
+        .find(|p| p.kind.is_none_or(|kind| kind == unavailable.kind()))
+        .map_or(unavailable.default_text(), |p| p.text.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_placeholder() {
+        let parse = |args: &[&str]| {
+            args.iter()
+                .map(|a| a.parse().unwrap())
+                .collect::<Vec<Placeholder>>()
+        };
+        let skew = Unavailable::Rate(RateError::ClockSkew);
+        assert_eq!(placeholder(&[], skew), "Clock skew");
+        let placeholders = parse(&["…", "clock-skew=?", "a=b"]);
+        assert_eq!(placeholder(&placeholders, skew), "a=b");
+        let placeholders = parse(&["…", "clock-skew=?"]);
+        assert_eq!(placeholder(&placeholders, skew), "?");
+        assert_eq!(placeholder(&placeholders, Unavailable::Storage), "…");
+        assert_eq!(placeholder(&[], Unavailable::Collect), "Collect error");
+        let placeholders = parse(&["…", "collect-error=!"]);
+        assert_eq!(placeholder(&placeholders, Unavailable::Collect), "!");
+        assert_eq!(
+            placeholder(&placeholders, RateError::NotEnoughData.into()),
+            "…"
+        );
+        let json = serde_json::to_string(&placeholders).unwrap();
+        assert_eq!(json, r#"["…","collect-error=!"]"#);
+        assert_eq!(
+            serde_json::from_str::<Vec<Placeholder>>(&json).unwrap(),
+            placeholders
+        );
+    }
+}
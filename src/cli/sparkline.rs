@@ -0,0 +1,82 @@
+use super::metric::{Metric, Selection};
+use diffmetrik::metrics::format::Unit;
+use diffmetrik::metrics::MetricRate;
+use serde::{Deserialize, Serialize};
+
+const BLOCKS: &[char] = &['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// For terminals and fonts without the block elements
+const ASCII: &[char] = &['_', '.', '-', '~', '=', '+', '*', '#'];
+
+/// History of a metric drawn as one character per rate, newest on the right
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Sparkline {
+    pub metric: Metric,
+    pub length: usize,
+    #[serde(default)]
+    pub ascii: bool,
+}
+
+impl Sparkline {
+    /// Draws the last `length` of `rates`, padded on the left so the width is
+    /// constant. Rates whose source failed to collect are left blank.
+    pub fn render(&self, rates: &[MetricRate], selection: &Selection) -> String {
+        let skip = rates.len().saturating_sub(self.length);
+        let values = rates[skip..]
+            .iter()
+            .map(|rate| {
+                if !self.metric.available(rate) {
+                    return None;
+                }
+                let values = self.metric.values(rate, selection, None);
+                Some(values.first().map_or(0.0, |(_, v)| *v))
+            })
+            .collect::<Vec<Option<f64>>>();
+        // Percentages have a natural scale, everything else is relative to the peak
+        let max = match self.metric.unit() {
+            Unit::Percent => 100.0,
+            _ => values.iter().flatten().cloned().fold(0.0, f64::max),
+        };
+        let levels = if self.ascii { ASCII } else { BLOCKS };
+        let line = spark(&values, max, levels);
+        let line = format!("{:>1$}", line, self.length);
+        match self.metric.label() {
+            Some(label) => format!("{}: {}", label, line),
+            None => line,
+        }
+    }
+}
+
+fn spark(values: &[Option<f64>], max: f64, levels: &[char]) -> String {
+    let top = (levels.len() - 1) as f64;
+    values
+        .iter()
+        .map(|v| {
+            let v = match v {
+                Some(v) => v,
+                None => return ' ',
+            };
+            let level = if max > 0.0 {
+                (v / max * top).round().max(0.0).min(top)
+            } else {
+                0.0
+            };
+            levels[level as usize]
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spark() {
+        let values = [Some(0.0), Some(1.0), Some(3.5), Some(7.0)];
+        assert_eq!(spark(&values, 7.0, BLOCKS), "▁▂▅█");
+        assert_eq!(spark(&values, 7.0, ASCII), "_.=#");
+        assert_eq!(spark(&[Some(0.0), Some(0.0)], 0.0, BLOCKS), "▁▁");
+        assert_eq!(spark(&[Some(200.0)], 100.0, BLOCKS), "█");
+        assert_eq!(spark(&[Some(7.0), None, Some(7.0)], 7.0, BLOCKS), "█ █");
+    }
+}
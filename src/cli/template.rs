@@ -0,0 +1,215 @@
+use super::highlight::Highlight;
+use super::metric::{Metric, Selection};
+use super::placeholder::Placeholder;
+use diffmetrik::metrics::format::Spec;
+use diffmetrik::metrics::MetricRate;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Literal(String),
+    Placeholder {
+        metric: Metric,
+        field: Option<String>,
+        spec: Spec,
+    },
+}
+
+/// Output template such as `D:{download} U:{upload} C:{cpu.m1:.1}`
+///
+/// Placeholders are `{metric[.field][:spec]}`, see `Metric::check_field` for
+/// fields and `Spec` for the format spec. Braces are escaped by doubling them.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Template {
+    source: String,
+    parts: Vec<Part>,
+}
+
+impl Template {
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn render(
+        &self,
+        rate: &MetricRate,
+        selection: &Selection,
+        highlight: &Highlight,
+        placeholders: &[Placeholder],
+    ) -> String {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Literal(s) => s.clone(),
+                Part::Placeholder {
+                    metric,
+                    field,
+                    spec,
+                } => metric.format(
+                    rate,
+                    selection,
+                    highlight,
+                    placeholders,
+                    field.as_deref(),
+                    spec,
+                ),
+            })
+            .collect()
+    }
+}
+
+fn parse_placeholder(s: &str) -> Result<Part, String> {
+    let (name, spec) = match s.split_once(':') {
+        Some((name, spec)) => (name, spec.parse::<Spec>()?),
+        None => (s, Spec::default()),
+    };
+    let (metric, field) = match name.split_once('.') {
+        Some((metric, field)) => (metric, Some(field.to_string())),
+        None => (name, None),
+    };
+    let metric = metric
+        .parse::<Metric>()
+        .map_err(|e| format!("unknown metric '{}' in template, {}", metric, e))?;
+    if let Some(field) = &field {
+        metric.check_field(field)?;
+    }
+    spec.check(metric.unit())?;
+    Ok(Part::Placeholder {
+        metric,
+        field,
+        spec,
+    })
+}
+
+impl std::str::FromStr for Template {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Template, String> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    let end = rest
+                        .find('}')
+                        .ok_or_else(|| format!("unclosed '{{' in template '{}'", s))?;
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(parse_placeholder(&rest[..end])?);
+                    chars = rest[end + 1..].chars();
+                }
+                '}' => return Err(format!("unmatched '}}' in template '{}'", s)),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Ok(Template {
+            source: s.to_string(),
+            parts,
+        })
+    }
+}
+
+impl std::convert::TryFrom<String> for Template {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Template, String> {
+        s.parse()
+    }
+}
+
+impl From<Template> for String {
+    fn from(template: Template) -> String {
+        template.source
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_template() {
+        let t: Template = "D:{download} {{C}}:{cpu.m1:.1}".parse().expect("valid");
+        assert_eq!(
+            t.parts,
+            vec![
+                Part::Literal("D:".to_string()),
+                Part::Placeholder {
+                    metric: "download".parse().unwrap(),
+                    field: None,
+                    spec: Spec::default(),
+                },
+                Part::Literal(" {C}:".to_string()),
+                Part::Placeholder {
+                    metric: "cpu".parse().unwrap(),
+                    field: Some("m1".to_string()),
+                    spec: ".1".parse().unwrap(),
+                },
+            ]
+        );
+        assert_eq!(t.source(), "D:{download} {{C}}:{cpu.m1:.1}");
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!("{download".parse::<Template>().is_err());
+        assert!("download}".parse::<Template>().is_err());
+        assert!("{bogus}".parse::<Template>().is_err());
+        assert!("{cpu.m7}".parse::<Template>().is_err());
+        assert!("{memory.eth0}".parse::<Template>().is_err());
+        assert!("{cpu-usage:.1M}".parse::<Template>().is_err());
+    }
+
+    #[test]
+    fn render_template() {
+        use diffmetrik::metrics::{CPUMetrics, FilesystemUsage};
+        let rate = MetricRate {
+            time: Default::default(),
+            window: std::time::Duration::new(2, 0),
+            cpu: CPUMetrics {
+                m1: 1.25,
+                m5: 0.5,
+                m15: 0.25,
+            },
+            filesystems: vec![FilesystemUsage {
+                mount_point: "/".to_string(),
+                total: 1000,
+                free: 500,
+                available: 500,
+            }],
+            ..MetricRate::default()
+        };
+        let render = |template: &str, rate: &MetricRate| {
+            let placeholders = ["collect-error=?".parse().unwrap()];
+            template.parse::<Template>().unwrap().render(
+                rate,
+                &Selection::default(),
+                &Highlight::default(),
+                &placeholders,
+            )
+        };
+        let t = "C:{cpu.m1:.1} {{{cpu.m15:.2}}} F:{disk-usage:.0}";
+        assert_eq!(render(t, &rate), "C:1.2 {0.25} F:/: 50%");
+        assert_eq!(render("F:{disk-usage./srv}", &rate), "F:?");
+        let failed = MetricRate {
+            failed: vec!["filesystems".to_string()],
+            ..rate
+        };
+        assert_eq!(render(t, &failed), "C:1.2 {0.25} F:?");
+    }
+}
@@ -0,0 +1,188 @@
+use crate::cli;
+use crate::ipc;
+use crate::ipc::{IpcError, Reply, Request, Response};
+use diffmetrik::metrics;
+use diffmetrik::metrics::{Metrics, RateError, Roots};
+use diffmetrik::storage;
+use diffmetrik::storage::Storage;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+type History = Arc<Mutex<Option<Metrics>>>;
+
+/// Samples the metrics every `--interval`, keeps the newest `--history` of
+/// them in memory and answers queries on the per-user socket.
+///
+/// Every sample is also persisted through `storage`, so one-shot invocations
+/// that cannot reach the socket still find recent history.
+pub fn run(opt: &cli::Opt, state_dir: &Path, storage: Storage) -> std::io::Result<()> {
+    let interval = opt.interval();
+    let capacity = opt.history();
+    let roots = opt.roots();
+    let selection = opt.selection();
+    let half_life = opt.averaging().half_life();
+    let debug = opt.debug;
+    let listener = bind(state_dir, debug)?;
+    if debug {
+        eprintln!("Starting daemon, sampling every {:?}", interval);
+    }
+    let history: History = Arc::new(Mutex::new(storage.read().ok()));
+    let sampler_history = Arc::clone(&history);
+    let sampler_roots = roots.clone();
+    std::thread::spawn(move || loop {
+        let sampled = crate::sample(&sampler_roots, &selection, debug);
+        let mut guard = sampler_history.lock().unwrap();
+        *guard = merge(guard.take(), sampled, capacity, debug);
+        if let Some(metrics) = guard.as_mut() {
+            // Refined by every sample, other half-lives are averaged per query
+            if let Some(half_life) = half_life {
+                metrics.smooth(half_life);
+            }
+            if let Err(e) = storage.write(metrics) {
+                eprintln!("{}", e);
+            }
+        }
+        drop(guard);
+        std::thread::sleep(interval);
+    });
+    for stream in listener.incoming() {
+        let result = stream
+            .map_err(IpcError::from)
+            .and_then(|stream| handle(&stream, &history, &roots));
+        if let Err(e) = result {
+            if debug {
+                eprintln!("Unable to answer query: {}", e);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn bind(state_dir: &Path, debug: bool) -> std::io::Result<UnixListener> {
+    storage::create_state_dir(state_dir)?;
+    let path = ipc::socket_path(state_dir);
+    if path.exists() {
+        if UnixStream::connect(&path).is_ok() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AddrInUse,
+                format!("Daemon is already listening on {:?}", path),
+            ));
+        }
+        // Left behind by a daemon that did not shut down cleanly
+        std::fs::remove_file(&path)?;
+    }
+    if debug {
+        eprintln!("Listening on: {:?}", &path);
+    }
+    UnixListener::bind(&path)
+}
+
+fn merge(
+    history: Option<Metrics>,
+    sampled: Result<Metrics, metrics::MetricError>,
+    capacity: usize,
+    debug: bool,
+) -> Option<Metrics> {
+    match (history, sampled) {
+        // The clock went backwards, the old samples would yield a negative window
+        (Some(old), Ok(new)) if went_backwards(&old, &new) => {
+            if debug {
+                eprintln!("Clock went backwards, dropping history");
+            }
+            Some(new)
+        }
+        (Some(old), Ok(new)) => Some(old.merge(new, capacity)),
+        (None, Ok(new)) => Some(new),
+        (history, Err(e)) => {
+            if debug {
+                eprintln!("Unable to sample metrics: {}", e);
+            }
+            history
+        }
+    }
+}
+
+fn went_backwards(old: &Metrics, new: &Metrics) -> bool {
+    match (old.latest(), new.latest()) {
+        (Some(old), Some(new)) => new.elapsed_since(old).is_none(),
+        _ => false,
+    }
+}
+
+fn handle(stream: &UnixStream, history: &History, roots: &Roots) -> Result<(), IpcError> {
+    stream.set_read_timeout(Some(ipc::TIMEOUT))?;
+    stream.set_write_timeout(Some(ipc::TIMEOUT))?;
+    let request: Request = ipc::receive(stream)?;
+    let reply = if request.version != ipc::PROTOCOL_VERSION {
+        Reply::Error {
+            message: IpcError::Version(request.version).to_string(),
+        }
+    } else if request.roots != *roots {
+        // The client samples on its own rather than getting another machine's values
+        Reply::Error {
+            message: format!("Sampling {}, not {}", roots, request.roots),
+        }
+    } else {
+        let history = history.lock().unwrap();
+        let rendered = match history.as_ref() {
+            Some(metrics) => request.query.render(
+                metrics,
+                &request.selection,
+                &request.highlight,
+                &request.averaging,
+                &request.placeholder,
+            ),
+            None => Err(RateError::NotEnoughData),
+        };
+        match rendered {
+            Ok(value) => Reply::Rate { value },
+            Err(reason) => Reply::Unavailable { reason },
+        }
+    };
+    ipc::send(stream, &Response::new(reply))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::{Highlight, Query, Selection};
+    use diffmetrik::metrics::Averaging;
+
+    #[test]
+    fn test_other_roots() {
+        let history: History = Arc::new(Mutex::new(None));
+        let ask = |roots: Roots| {
+            let (client, server) = UnixStream::pair().expect("socket pair");
+            let request = Request {
+                version: ipc::PROTOCOL_VERSION,
+                query: Query::Metric("download".parse().unwrap()),
+                selection: Selection::default(),
+                highlight: Highlight::default(),
+                averaging: Averaging::default(),
+                placeholder: Vec::new(),
+                roots,
+            };
+            ipc::send(&client, &request).expect("sent");
+            handle(&server, &history, &Roots::default()).expect("handled");
+            ipc::receive::<Response>(&client).expect("received").reply
+        };
+        assert_eq!(
+            ask(Roots::default()),
+            Reply::Unavailable {
+                reason: RateError::NotEnoughData
+            }
+        );
+        let host = Roots {
+            proc: "/host/proc".into(),
+            sys: "/host/sys".into(),
+            host: "/host".into(),
+        };
+        assert_eq!(
+            ask(host),
+            Reply::Error {
+                message: "Sampling proc=/proc sys=/sys host=/, not proc=/host/proc sys=/host/sys host=/host".to_string()
+            }
+        );
+    }
+}
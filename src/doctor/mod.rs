@@ -0,0 +1,117 @@
+use crate::cli;
+use crate::ipc;
+use diffmetrik::metrics;
+use diffmetrik::metrics::{Clocks, Metrics};
+use diffmetrik::storage;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::Duration;
+
+/// Only failures make the doctor exit unsuccessfully
+enum Status {
+    Ok,
+    Warn,
+    Fail,
+}
+
+/// Checks the config, the state directory, the stored history, the daemon,
+/// the clocks and every metric source, printing a line for each.
+///
+/// Returns false if any of them failed.
+pub fn run(opt: &cli::Opt, state_dir: &Path) -> bool {
+    let mut healthy = true;
+    let mut report = |status: Status, check: &str, detail: String| {
+        let status = match status {
+            Status::Ok => "ok",
+            Status::Warn => "warn",
+            Status::Fail => {
+                healthy = false;
+                "FAIL"
+            }
+        };
+        println!("{:<5} {:<18} {}", status, check, detail);
+    };
+
+    // A config that does not parse is reported before any subcommand runs
+    match opt.config.clone().or_else(cli::config_path) {
+        Some(path) if path.exists() => {
+            let profile = opt.profile.as_deref().unwrap_or("default");
+            report(
+                Status::Ok,
+                "config",
+                format!("{}, profile {}", path.display(), profile),
+            )
+        }
+        Some(path) => report(
+            Status::Ok,
+            "config",
+            format!("{} does not exist, only flags are used", path.display()),
+        ),
+        None => report(
+            Status::Warn,
+            "config",
+            "neither XDG_CONFIG_HOME nor HOME is set".to_string(),
+        ),
+    }
+
+    match storage::create_state_dir(state_dir) {
+        Ok(()) => report(
+            Status::Ok,
+            "state directory",
+            state_dir.display().to_string(),
+        ),
+        Err(e) => report(
+            Status::Fail,
+            "state directory",
+            format!("{}: {}", state_dir.display(), e),
+        ),
+    }
+
+    let storage = storage::Storage::new(
+        state_dir,
+        opt.file_name.clone(),
+        Duration::new(opt.min_interval, 0),
+    );
+    match storage.read::<Metrics>() {
+        Ok(history) => report(
+            Status::Ok,
+            "history",
+            format!("{} rates in {}", history.rates().len(), opt.file_name),
+        ),
+        Err(e) => report(
+            Status::Warn,
+            "history",
+            format!("{}, it is replaced by the next call", e),
+        ),
+    }
+
+    match UnixStream::connect(ipc::socket_path(state_dir)) {
+        Ok(_) => report(Status::Ok, "daemon", "running".to_string()),
+        Err(_) => report(
+            Status::Warn,
+            "daemon",
+            "not running, every call samples on its own".to_string(),
+        ),
+    }
+
+    match Clocks::now() {
+        Some(_) => report(
+            Status::Ok,
+            "clocks",
+            "monotonic and boot clocks".to_string(),
+        ),
+        None => report(
+            Status::Warn,
+            "clocks",
+            "unavailable, rates are timed by the wall clock".to_string(),
+        ),
+    }
+
+    for (source, result) in metrics::check_sources(&opt.roots(), &opt.selection()) {
+        match result {
+            Ok(()) => report(Status::Ok, "source", source.to_string()),
+            Err(e) => report(Status::Fail, "source", format!("{}: {}", source, e)),
+        }
+    }
+    healthy
+}
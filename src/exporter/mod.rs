@@ -0,0 +1,247 @@
+use diffmetrik::metrics::{FamilyKind, Roots, Selection, TimeTaggedMetric};
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::net::{TcpListener, TcpStream};
+use std::time::Duration;
+
+/// How long a scraper may take to send its request
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Serves the current metrics on `/metrics` in the Prometheus text exposition format.
+///
+/// Metrics are collected on every scrape, counters are exported as totals so
+/// that rates are computed by Prometheus over its own scrape interval.
+pub fn run(
+    address: &str,
+    roots: &Roots,
+    selection: &Selection,
+    debug: bool,
+) -> std::io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    if debug {
+        eprintln!(
+            "Serving metrics on http://{}/metrics",
+            listener.local_addr()?
+        );
+    }
+    for stream in listener.incoming() {
+        if let Err(e) = stream.and_then(|stream| handle(&stream, roots, selection, debug)) {
+            if debug {
+                eprintln!("Unable to answer scrape: {}", e);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn handle(
+    mut stream: &TcpStream,
+    roots: &Roots,
+    selection: &Selection,
+    debug: bool,
+) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // Headers are not needed, but have to be read before the response is sent
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 0 && !header.trim().is_empty() {
+        header.clear();
+    }
+    let mut parts = request_line.split_whitespace();
+    let path = parts.next().and(parts.next()).unwrap_or_default();
+    let (status, body) = match (request_line.starts_with("GET "), path.split('?').next()) {
+        (true, Some("/metrics")) => match crate::sample(roots, selection, debug) {
+            Ok(m) => match m.latest() {
+                Some(latest) => ("200 OK", render(latest)),
+                None => ("500 Internal Server Error", "No sample\n".to_string()),
+            },
+            Err(e) => ("500 Internal Server Error", format!("{}\n", e)),
+        },
+        (true, _) => (
+            "404 Not Found",
+            "Metrics are served on /metrics\n".to_string(),
+        ),
+        (false, _) => (
+            "405 Method Not Allowed",
+            "Only GET is supported\n".to_string(),
+        ),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        CONTENT_TYPE,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+/// Accumulates metric families, each family is written with its HELP and TYPE lines
+struct Exposition {
+    out: String,
+}
+
+impl Exposition {
+    fn family(&mut self, name: &str, kind: &str, help: &str) -> &mut Exposition {
+        self.out
+            .push_str(&format!("# HELP diffmetrik_{} {}\n", name, help));
+        self.out
+            .push_str(&format!("# TYPE diffmetrik_{} {}\n", name, kind));
+        self
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, String)], value: f64) -> &mut Exposition {
+        let labels = labels
+            .iter()
+            .map(|(k, v)| format!("{}=\"{}\"", k, escape_label(v)))
+            .collect::<Vec<String>>();
+        let value = match value {
+            v if v.is_nan() => "NaN".to_string(),
+            v if v == f64::INFINITY => "+Inf".to_string(),
+            v if v == f64::NEG_INFINITY => "-Inf".to_string(),
+            v => v.to_string(),
+        };
+        if labels.is_empty() {
+            self.out
+                .push_str(&format!("diffmetrik_{} {}\n", name, value));
+        } else {
+            self.out.push_str(&format!(
+                "diffmetrik_{}{{{}}} {}\n",
+                name,
+                labels.join(","),
+                value
+            ));
+        }
+        self
+    }
+}
+
+fn escape_label(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Families of sources that failed to collect are left out, a missing series
+/// is better than one reporting zero
+fn render(m: &TimeTaggedMetric) -> String {
+    let mut e = Exposition { out: String::new() };
+    for family in m.families() {
+        let kind = match family.kind {
+            FamilyKind::Counter => "counter",
+            FamilyKind::Gauge => "gauge",
+        };
+        e.family(family.name, kind, family.help);
+        for (labels, value) in (family.samples)(m) {
+            e.sample(family.name, &labels, value);
+        }
+    }
+    e.out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diffmetrik::metrics::{
+        CPUMetrics, CpuStat, CpuTimes, FilesystemUsage, InterfaceMetrics, MemoryMetrics,
+        NetworkMetrics,
+    };
+
+    fn sample() -> TimeTaggedMetric {
+        let mut network = NetworkMetrics::default();
+        network.interfaces.insert(
+            "eth0".to_string(),
+            InterfaceMetrics {
+                ibytes: 1000,
+                obytes: 2000,
+                physical: true,
+                ..InterfaceMetrics::default()
+            },
+        );
+        let mut cpu_stat = CpuStat::default();
+        cpu_stat.cores.push(CpuTimes {
+            user: 250,
+            idle: 1000,
+            ..CpuTimes::default()
+        });
+        TimeTaggedMetric {
+            time: Duration::new(0, 0),
+            network,
+            cpu: CPUMetrics {
+                m1: 0.5,
+                m5: 0.25,
+                m15: 0.125,
+            },
+            cpu_stat,
+            memory: MemoryMetrics {
+                total: 4096,
+                ..MemoryMetrics::default()
+            },
+            filesystems: vec![FilesystemUsage {
+                mount_point: "/mnt/backup disk".to_string(),
+                total: 100,
+                free: 50,
+                available: 40,
+            }],
+            ..TimeTaggedMetric::default()
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let out = render(&sample());
+        let lines = out.lines().collect::<Vec<&str>>();
+        assert!(lines.contains(&"# TYPE diffmetrik_network_receive_bytes_total counter"));
+        assert!(lines.contains(&"diffmetrik_network_receive_bytes_total{interface=\"eth0\"} 1000"));
+        assert!(lines.contains(&"diffmetrik_network_physical{interface=\"eth0\"} 1"));
+        assert!(lines.contains(&"diffmetrik_load1 0.5"));
+        assert!(lines.contains(&"# TYPE diffmetrik_cpu_seconds_total counter"));
+        assert!(lines
+            .iter()
+            .any(|l| l.starts_with("diffmetrik_cpu_seconds_total{cpu=\"0\",mode=\"user\"} ")));
+        assert!(lines.contains(&"diffmetrik_memory_total_bytes 4096"));
+        assert!(lines
+            .contains(&"diffmetrik_filesystem_avail_bytes{mountpoint=\"/mnt/backup disk\"} 40"));
+
+        let failed = TimeTaggedMetric {
+            failed: vec!["cpu".to_string(), "memory".to_string()],
+            ..sample()
+        };
+        let out = render(&failed);
+        assert!(!out.contains("diffmetrik_load1"));
+        assert!(!out.contains("diffmetrik_memory_total_bytes"));
+        assert!(out.contains("diffmetrik_cpu_seconds_total{cpu=\"0\",mode=\"user\"} "));
+    }
+
+    #[test]
+    fn test_escape_label() {
+        assert_eq!(escape_label(r#"a"b\c"#), r#"a\"b\\c"#);
+        assert_eq!(escape_label("a\nb"), "a\\nb");
+    }
+
+    #[test]
+    fn test_handle() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bound");
+        let address = listener.local_addr().unwrap();
+        let get = |path: &str| {
+            let mut client = TcpStream::connect(address).expect("connected");
+            write!(client, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+            let (server, _) = listener.accept().expect("accepted");
+            handle(&server, &Roots::default(), &Selection::default(), false).expect("handled");
+            drop(server);
+            let mut response = String::new();
+            client.read_to_string(&mut response).unwrap();
+            response
+        };
+        assert!(get("/").starts_with("HTTP/1.1 404 Not Found\r\n"));
+        let response = get("/metrics");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("\r\n\r\n# HELP diffmetrik_"));
+        assert!(response.contains("\ndiffmetrik_load1 "));
+    }
+}
@@ -0,0 +1,213 @@
+use crate::cli::{Highlight, Placeholder, Query, Selection};
+use diffmetrik::metrics::{Averaging, RateError, Roots};
+use serde::{Deserialize, Serialize};
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Bumped whenever `Request` or `Response` change incompatibly
+pub const PROTOCOL_VERSION: u32 = 9;
+
+/// How long a client waits for the daemon before falling back to storage
+pub const TIMEOUT: Duration = Duration::from_millis(200);
+
+#[derive(Debug)]
+pub enum IpcError {
+    IO(std::io::Error),
+    Serialization(serde_json::Error),
+    Version(u32),
+    Daemon(String),
+}
+
+impl std::fmt::Display for IpcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            IpcError::IO(e) => write!(f, "{}", e),
+            IpcError::Serialization(e) => write!(f, "{}", e),
+            IpcError::Version(v) => write!(
+                f,
+                "Unsupported protocol version {}, expected {}",
+                v, PROTOCOL_VERSION
+            ),
+            IpcError::Daemon(e) => write!(f, "Daemon error: {}", e),
+        }
+    }
+}
+
+impl From<std::io::Error> for IpcError {
+    fn from(e: std::io::Error) -> IpcError {
+        IpcError::IO(e)
+    }
+}
+
+impl From<serde_json::Error> for IpcError {
+    fn from(e: serde_json::Error) -> IpcError {
+        IpcError::Serialization(e)
+    }
+}
+
+/// Asks the daemon to render the current rate of a metric or a template
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Request {
+    pub version: u32,
+    pub query: Query,
+    #[serde(flatten)]
+    pub selection: Selection,
+    #[serde(default)]
+    pub highlight: Highlight,
+    #[serde(flatten)]
+    pub averaging: Averaging,
+    /// Printed in place of metrics whose source failed to collect
+    #[serde(default)]
+    pub placeholder: Vec<Placeholder>,
+    /// The daemon only answers for the roots it samples
+    #[serde(default)]
+    pub roots: Roots,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Reply {
+    Rate { value: String },
+    Unavailable { reason: RateError },
+    Error { message: String },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Response {
+    pub version: u32,
+    #[serde(flatten)]
+    pub reply: Reply,
+}
+
+impl Response {
+    pub fn new(reply: Reply) -> Response {
+        Response {
+            version: PROTOCOL_VERSION,
+            reply,
+        }
+    }
+}
+
+/// Location of the daemon socket in the state directory
+pub fn socket_path(state_dir: &Path) -> PathBuf {
+    state_dir.join("diffmetrik.sock")
+}
+
+/// Queries a running daemon, the inner error tells why it has no rate to render
+pub fn query_rate(
+    state_dir: &Path,
+    query: &Query,
+    selection: &Selection,
+    highlight: &Highlight,
+    averaging: &Averaging,
+    placeholders: &[Placeholder],
+    roots: &Roots,
+) -> Result<Result<String, RateError>, IpcError> {
+    let stream = UnixStream::connect(socket_path(state_dir))?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let request = Request {
+        version: PROTOCOL_VERSION,
+        query: query.clone(),
+        selection: selection.clone(),
+        highlight: highlight.clone(),
+        averaging: *averaging,
+        placeholder: placeholders.to_vec(),
+        roots: roots.clone(),
+    };
+    send(&stream, &request)?;
+    let response: Response = receive(&stream)?;
+    if response.version != PROTOCOL_VERSION {
+        return Err(IpcError::Version(response.version));
+    }
+    match response.reply {
+        Reply::Rate { value } => Ok(Ok(value)),
+        Reply::Unavailable { reason } => Ok(Err(reason)),
+        Reply::Error { message } => Err(IpcError::Daemon(message)),
+    }
+}
+
+/// Messages are single lines of JSON
+pub fn send<T: Serialize>(mut stream: &UnixStream, message: &T) -> Result<(), IpcError> {
+    let mut line = serde_json::to_string(message)?;
+    line.push('\n');
+    stream.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+pub fn receive<T>(stream: &UnixStream) -> Result<T, IpcError>
+where
+    for<'de> T: Deserialize<'de>,
+{
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    Ok(serde_json::from_str(&line)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_round_trip() {
+        let (client, server) = UnixStream::pair().expect("socket pair");
+        let request = Request {
+            version: PROTOCOL_VERSION,
+            query: Query::Metric("download".parse().unwrap()),
+            selection: Selection::default(),
+            highlight: Highlight::default(),
+            averaging: Averaging::default(),
+            placeholder: Vec::new(),
+            roots: Roots::default(),
+        };
+        send(&client, &request).expect("sent");
+        let received: Request = receive(&server).expect("received");
+        assert_eq!(received.version, PROTOCOL_VERSION);
+        assert_eq!(received.query, Query::Metric("download".parse().unwrap()));
+    }
+
+    #[test]
+    fn response_wire_format() {
+        let response = Response::new(Reply::Rate {
+            value: "D: 1.00 kB/s".to_string(),
+        });
+        let json = serde_json::to_string(&response).expect("serialized");
+        assert_eq!(
+            json,
+            r#"{"version":9,"status":"rate","value":"D: 1.00 kB/s"}"#
+        );
+        let parsed: Response =
+            serde_json::from_str(r#"{"version":9,"status":"unavailable","reason":"clock_skew"}"#)
+                .expect("parsed");
+        assert_eq!(
+            parsed.reply,
+            Reply::Unavailable {
+                reason: RateError::ClockSkew
+            }
+        );
+    }
+
+    #[test]
+    fn request_wire_format() {
+        let request = Request {
+            version: PROTOCOL_VERSION,
+            query: Query::Format("D:{download}".parse().unwrap()),
+            selection: Selection::default(),
+            highlight: Highlight::default(),
+            averaging: Averaging::default(),
+            placeholder: Vec::new(),
+            roots: Roots::default(),
+        };
+        let json = serde_json::to_string(&request).expect("serialized");
+        assert_eq!(
+            json,
+            r#"{"version":9,"query":{"format":"D:{download}"},"device":null,"mounts":[],"interfaces":[],"highlight":{"warn":[],"crit":[],"warn_style":"fg=yellow","crit_style":"fg=red"},"window":null,"half_life":null,"placeholder":[],"roots":{"proc":"/proc","sys":"/sys","host":"/"}}"#
+        );
+        let parsed: Request =
+            serde_json::from_str(r#"{"version":9,"query":{"metric":"upload"}}"#).expect("parsed");
+        assert_eq!(parsed.query, Query::Metric("upload".parse().unwrap()));
+    }
+}
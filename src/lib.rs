@@ -0,0 +1,29 @@
+#![warn(missing_docs)]
+//! Collectors for vital system metrics and the math to turn their counters
+//! into rates.
+//!
+//! A sample of every source is taken with [`metrics::get_metrics`]. Samples
+//! are merged into a history, which computes rates between its samples, and
+//! [`storage::Storage`] keeps the history between calls:
+//!
+//! ```no_run
+//! use diffmetrik::metrics::{self, Averaging};
+//! use diffmetrik::storage::{self, Storage};
+//! use std::time::Duration;
+//!
+//! let dir = storage::state_dir(None);
+//! let storage = Storage::new(&dir, "status.json".to_string(), Duration::new(2, 0));
+//! let mut history = metrics::get_metrics()?;
+//! if let Ok(old) = storage.read::<metrics::Metrics>() {
+//!     history = old.merge(history, 3);
+//! }
+//! storage.write(&history)?;
+//! match history.rate(&Averaging::default()) {
+//!     Ok(rate) => println!("{:.0} B/s down", rate.network.interfaces["eth0"].ibytes),
+//!     Err(e) => println!("{}", e),
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+pub mod metrics;
+pub mod storage;
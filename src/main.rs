@@ -1,41 +1,189 @@
 mod cli;
-mod metrics;
-mod storage;
+mod daemon;
+mod doctor;
+mod exporter;
+mod ipc;
+
+use diffmetrik::{metrics, storage};
+use std::path::Path;
+use std::time::Duration;
 
 fn main() {
     let opt = cli::opt_from_args();
-    let storage = storage::Storage::new(opt.file_name, std::time::Duration::new(2, 0), opt.debug);
-    let old_metrics: Option<metrics::Metrics> = storage
-        .read()
-        .map_err(|e| {
-            storage.reset().unwrap();
-            e
-        })
-        .ok();
-    let metrics = metrics::get_metrics().ok();
-    let write_error = "Unable to write temp file with the metrics";
-
-    match (old_metrics, metrics) {
-        (Some(old), Some(new)) => {
-            let metrics = old.merge(new);
-            //dbg!(&metrics);
-            storage.write(&metrics).expect(write_error);
-            let metric_rate: Option<metrics::MetricRate> = metrics.get_rate();
-            match metric_rate {
-                Some(r) => match opt.metric {
-                    cli::Metric::Cpu => println!("C: {:.2}", r.cpu.m1),
-                    cli::Metric::Download => println!("D: {}", r.network.ibyte_rate),
-                    cli::Metric::Upload => println!("U: {}", r.network.obyte_rate),
-                },
-                None => {
-                    storage.write(&metrics).expect(write_error);
-                    println!("Not enough data");
-                }
+    let state_dir = storage::state_dir(opt.state_dir.clone());
+    match opt.command() {
+        cli::Command::Get { .. } => println!("{}", get(&opt, &state_dir, &query(&opt))),
+        cli::Command::Watch { .. } => {
+            let query = query(&opt);
+            loop {
+                println!("{}", get(&opt, &state_dir, &query));
+                std::thread::sleep(opt.interval());
+            }
+        }
+        cli::Command::History { metric } => {
+            println!("{}", get(&opt, &state_dir, &cli::Query::History(metric)))
+        }
+        cli::Command::Daemon => {
+            // The daemon persists every sample it takes, so it must not be throttled by storage
+            let storage = open_storage(&opt, &state_dir, Duration::new(0, 0));
+            exit_on_error(daemon::run(&opt, &state_dir, storage));
+        }
+        cli::Command::Serve => exit_on_error(exporter::run(
+            &opt.listen,
+            &opt.roots(),
+            &opt.selection(),
+            opt.debug,
+        )),
+        cli::Command::List => {
+            let width = |column: fn(&cli::Metric) -> &'static str| {
+                cli::Metric::all()
+                    .map(|m| column(&m).len())
+                    .max()
+                    .unwrap_or(0)
+            };
+            let name = width(|m| m.name());
+            let label = width(|m| m.label().unwrap_or_default());
+            let unit = width(|m| m.unit().suffix());
+            for metric in cli::Metric::all() {
+                let line = format!(
+                    "{:<name$} {:<label$} {:<unit$} {}",
+                    metric.name(),
+                    metric.label().unwrap_or_default(),
+                    metric.unit().suffix(),
+                    if metric.multiple() { "multiple" } else { "" },
+                    name = name,
+                    label = label,
+                    unit = unit,
+                );
+                println!("{}", line.trim_end());
+            }
+        }
+        cli::Command::Reset => exit_on_error(one_shot_storage(&opt, &state_dir).reset()),
+        cli::Command::Doctor => {
+            if !doctor::run(&opt, &state_dir) {
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+/// Samples every source, sources that fail are marked in the sample and only logged
+fn sample(
+    roots: &metrics::Roots,
+    selection: &metrics::Selection,
+    debug: bool,
+) -> Result<metrics::Metrics, metrics::MetricError> {
+    let (metrics, failures) = metrics::get_metrics_from(roots, selection)?;
+    if debug {
+        for (source, e) in failures {
+            eprintln!("Unable to collect {}: {}", source, e);
+        }
+    }
+    Ok(metrics)
+}
+
+fn query(opt: &cli::Opt) -> cli::Query {
+    opt.query()
+        .expect("a metric or --format is checked for when parsing the arguments")
+}
+
+fn exit_on_error<E: std::fmt::Display>(result: Result<(), E>) {
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+fn open_storage(opt: &cli::Opt, state_dir: &Path, min_duration: Duration) -> storage::Storage {
+    let storage = storage::Storage::new(state_dir, opt.file_name.clone(), min_duration);
+    if opt.debug {
+        eprintln!("Storing data in: {:?}", storage.path());
+    }
+    storage
+}
+
+fn one_shot_storage(opt: &cli::Opt, state_dir: &Path) -> storage::Storage {
+    open_storage(opt, state_dir, Duration::new(opt.min_interval, 0))
+}
+
+/// Asks the daemon to render `query`, or samples on its own if it is not running
+fn get(opt: &cli::Opt, state_dir: &Path, query: &cli::Query) -> String {
+    let selection = opt.selection();
+    let highlight = opt.highlight();
+    let averaging = opt.averaging();
+
+    match ipc::query_rate(
+        state_dir,
+        query,
+        &selection,
+        &highlight,
+        &averaging,
+        &opt.placeholder,
+        &opt.roots(),
+    ) {
+        Ok(Ok(value)) => return value,
+        Ok(Err(reason)) => return cli::placeholder(&opt.placeholder, reason.into()).to_string(),
+        Err(e) => {
+            if opt.debug {
+                eprintln!("Daemon not available, falling back to storage: {}", e);
             }
         }
-        (_, metrics) => {
-            storage.write(&metrics).expect(write_error);
-            println!("Not enough data");
+    }
+
+    let storage = one_shot_storage(opt, state_dir);
+    match one_shot(opt, &storage, query, &selection, &highlight, &averaging) {
+        Ok(value) => value,
+        Err(unavailable) => cli::placeholder(&opt.placeholder, unavailable).to_string(),
+    }
+}
+
+/// Samples once, merges with the stored history and renders the query
+fn one_shot(
+    opt: &cli::Opt,
+    storage: &storage::Storage,
+    query: &cli::Query,
+    selection: &cli::Selection,
+    highlight: &cli::Highlight,
+    averaging: &metrics::Averaging,
+) -> Result<String, cli::Unavailable> {
+    let storage_error = |e: storage::StorageError| {
+        if opt.debug {
+            eprintln!("{}", e);
+        }
+        cli::Unavailable::Storage
+    };
+    // Missing or unreadable history is replaced by the next write
+    let old_metrics: Option<metrics::Metrics> = match storage.read() {
+        Ok(metrics) => Some(metrics),
+        Err(e) => {
+            if opt.debug {
+                eprintln!("Discarding stored metrics: {}", e);
+            }
+            None
+        }
+    };
+    let new_metrics = sample(&opt.roots(), selection, opt.debug).map_err(|e| {
+        if opt.debug {
+            eprintln!("Unable to sample metrics: {}", e);
+        }
+        cli::Unavailable::Collect
+    })?;
+    let mut metrics = match old_metrics {
+        Some(old) => old.merge(new_metrics, opt.history()),
+        None => new_metrics,
+    };
+    if let Some(half_life) = averaging.half_life() {
+        metrics.smooth(half_life);
+    }
+    match query.render(&metrics, selection, highlight, averaging, &opt.placeholder) {
+        Err(metrics::RateError::ClockSkew) => {
+            // Start over rather than reporting skew until the clock catches up
+            storage.reset().map_err(storage_error)?;
+            Err(metrics::RateError::ClockSkew.into())
+        }
+        rendered => {
+            storage.write(&metrics).map_err(storage_error)?;
+            Ok(rendered?)
         }
     }
 }
@@ -0,0 +1,73 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Readings of the kernel clocks that are not affected by setting the wall
+/// clock. Both start at boot, so readings can only be compared within a boot.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Clocks {
+    /// Time the system was awake, stops during suspend
+    pub monotonic: Duration,
+    /// Time since boot, including suspend
+    pub boottime: Duration,
+}
+
+impl Clocks {
+    /// Reads both clocks, `None` if the system does not provide them
+    pub fn now() -> Option<Clocks> {
+        Some(Clocks {
+            monotonic: clock_gettime(AWAKE_CLOCK)?,
+            boottime: clock_gettime(BOOT_CLOCK)?,
+        })
+    }
+
+    /// Time the system was awake since `older`, `None` if it is not older
+    pub fn elapsed_since(&self, older: &Clocks) -> Option<Duration> {
+        self.monotonic.checked_sub(older.monotonic)
+    }
+
+    /// Time the system was suspended since `older`
+    pub fn suspended_since(&self, older: &Clocks) -> Duration {
+        let total = self.boottime.saturating_sub(older.boottime);
+        total.saturating_sub(self.monotonic.saturating_sub(older.monotonic))
+    }
+}
+
+// macOS names its clocks differently, CLOCK_MONOTONIC keeps counting during sleep there
+#[cfg(target_os = "linux")]
+const AWAKE_CLOCK: libc::clockid_t = libc::CLOCK_MONOTONIC;
+#[cfg(target_os = "linux")]
+const BOOT_CLOCK: libc::clockid_t = libc::CLOCK_BOOTTIME;
+#[cfg(target_os = "macos")]
+const AWAKE_CLOCK: libc::clockid_t = libc::CLOCK_UPTIME_RAW;
+#[cfg(target_os = "macos")]
+const BOOT_CLOCK: libc::clockid_t = libc::CLOCK_MONOTONIC;
+
+fn clock_gettime(clock: libc::clockid_t) -> Option<Duration> {
+    let mut ts: libc::timespec = unsafe { std::mem::zeroed() };
+    if unsafe { libc::clock_gettime(clock, &mut ts) } != 0 {
+        return None;
+    }
+    Some(Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clocks() {
+        let clocks = Clocks::now().unwrap();
+        assert!(clocks.boottime >= clocks.monotonic);
+        let asleep = Clocks {
+            monotonic: Duration::new(12, 0),
+            boottime: Duration::new(8 * 3600 + 20, 0),
+        };
+        let awake = Clocks {
+            monotonic: Duration::new(10, 0),
+            boottime: Duration::new(18, 0),
+        };
+        assert_eq!(asleep.elapsed_since(&awake), Some(Duration::new(2, 0)));
+        assert_eq!(awake.elapsed_since(&asleep), None);
+        assert_eq!(asleep.suspended_since(&awake), Duration::new(8 * 3600, 0));
+    }
+}
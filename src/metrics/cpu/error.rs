@@ -1,12 +1,16 @@
+/// Reading the load averages or CPU times failed
 #[derive(Debug)]
 pub enum CpuError {
-    #[allow(dead_code)]
+    /// The CPU statistics could not be read or parsed
     GetMetrics(String),
-    #[allow(dead_code)]
+    /// A sysctl value could not be read
     CtlError,
-    #[allow(dead_code)]
+    /// Reading a statistics file failed
     IO(std::io::Error),
+    /// A load average is not a number
     ParseError(std::num::ParseFloatError),
+    /// A CPU time counter is not a number
+    ParseIntError(std::num::ParseIntError),
 }
 
 impl std::fmt::Display for CpuError {
@@ -16,6 +20,7 @@ impl std::fmt::Display for CpuError {
             CpuError::GetMetrics(e) => write!(f, "{}", &e),
             CpuError::IO(e) => write!(f, "{}", &e),
             CpuError::ParseError(e) => write!(f, "{}", &e),
+            CpuError::ParseIntError(e) => write!(f, "{}", &e),
         }
     }
 }
@@ -25,3 +30,9 @@ impl From<std::num::ParseFloatError> for CpuError {
         CpuError::ParseError(e)
     }
 }
+
+impl From<std::num::ParseIntError> for CpuError {
+    fn from(e: std::num::ParseIntError) -> CpuError {
+        CpuError::ParseIntError(e)
+    }
+}
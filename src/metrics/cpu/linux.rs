@@ -1,9 +1,11 @@
 use super::error::CpuError;
 use super::CPUMetrics;
+use super::{CpuStat, CpuTimes};
+use crate::metrics::Roots;
 
 #[cfg(target_os = "linux")]
-pub fn get_cpu_metrics() -> Result<CPUMetrics, CpuError> {
-    let text = std::fs::read_to_string("/proc/loadavg").map_err(CpuError::IO)?;
+pub fn get_cpu_metrics(roots: &Roots) -> Result<CPUMetrics, CpuError> {
+    let text = std::fs::read_to_string(roots.proc("loadavg")).map_err(CpuError::IO)?;
     let parsed = text
         .split(' ')
         .take(3)
@@ -15,3 +17,54 @@ pub fn get_cpu_metrics() -> Result<CPUMetrics, CpuError> {
         m15: parsed[2],
     })
 }
+
+#[cfg(target_os = "linux")]
+pub fn get_cpu_stat(roots: &Roots) -> Result<CpuStat, CpuError> {
+    let text = std::fs::read_to_string(roots.proc("stat")).map_err(CpuError::IO)?;
+    parse_linux_proc_stat(&text)
+}
+
+#[cfg(target_os = "linux")]
+fn parse_linux_proc_stat(s: &str) -> Result<CpuStat, CpuError> {
+    let mut stat = CpuStat::default();
+    for line in s.lines().filter(|line| line.starts_with("cpu")) {
+        let mut fields = line.split_whitespace();
+        let name = fields.next().unwrap_or_default();
+        let mut ticks = fields
+            .map(|n| n.parse::<u64>())
+            .collect::<Result<Vec<u64>, std::num::ParseIntError>>()?;
+        // Older kernels report fewer columns
+        ticks.resize(8, 0);
+        let times = CpuTimes {
+            user: ticks[0],
+            nice: ticks[1],
+            system: ticks[2],
+            idle: ticks[3],
+            iowait: ticks[4],
+            irq: ticks[5],
+            softirq: ticks[6],
+            steal: ticks[7],
+        };
+        if name == "cpu" {
+            stat.total = times;
+        } else {
+            stat.cores.push(times);
+        }
+    }
+    Ok(stat)
+}
+
+#[cfg(test)]
+mod tests {
+    static LINUX_PROC_STAT: &str = include_str!("../test/root/proc/stat");
+
+    #[test]
+    fn test_linux() {
+        let stat = super::parse_linux_proc_stat(LINUX_PROC_STAT).ok().unwrap();
+        assert_eq!(stat.total.user, 2255);
+        assert_eq!(stat.total.idle, 22625563);
+        assert_eq!(stat.total.softirq, 456);
+        assert_eq!(stat.cores.len(), 2);
+        assert_eq!(stat.cores[1].system, 849);
+    }
+}
@@ -1,5 +1,7 @@
 use super::error::CpuError;
 use super::CPUMetrics;
+use super::{CpuStat, CpuTimes};
+use crate::metrics::Roots;
 use sysctl::Sysctl;
 
 #[repr(C)]
@@ -10,7 +12,7 @@ struct loadavg {
 }
 
 #[cfg(target_os = "macos")]
-pub fn get_cpu_metrics() -> Result<CPUMetrics, CpuError> {
+pub fn get_cpu_metrics(_: &Roots) -> Result<CPUMetrics, CpuError> {
     let ctl = sysctl::Ctl::new("vm.loadavg").map_err(|_| CpuError::CtlError)?;
     let vval = ctl.value().map_err(|_| CpuError::CtlError)?;
     if let sysctl::CtlValue::Struct(sval) = vval {
@@ -26,3 +28,55 @@ pub fn get_cpu_metrics() -> Result<CPUMetrics, CpuError> {
         ))
     }
 }
+
+/// Per-core tick counters from the mach host, macOS has no iowait or steal
+#[cfg(target_os = "macos")]
+#[allow(deprecated)]
+pub fn get_cpu_stat(_: &Roots) -> Result<CpuStat, CpuError> {
+    let mut count: libc::natural_t = 0;
+    let mut info: libc::processor_info_array_t = std::ptr::null_mut();
+    let mut info_count: libc::mach_msg_type_number_t = 0;
+    let result = unsafe {
+        libc::host_processor_info(
+            libc::mach_host_self(),
+            libc::PROCESSOR_CPU_LOAD_INFO,
+            &mut count,
+            &mut info,
+            &mut info_count,
+        )
+    };
+    if result != libc::KERN_SUCCESS {
+        return Err(CpuError::GetMetrics(format!(
+            "host_processor_info failed with {}",
+            result
+        )));
+    }
+    let ticks = unsafe { std::slice::from_raw_parts(info, info_count as usize) };
+    let states = libc::CPU_STATE_MAX as usize;
+    let cores = ticks
+        .chunks(states)
+        .take(count as usize)
+        .map(|core| CpuTimes {
+            user: core[libc::CPU_STATE_USER as usize] as u64,
+            nice: core[libc::CPU_STATE_NICE as usize] as u64,
+            system: core[libc::CPU_STATE_SYSTEM as usize] as u64,
+            idle: core[libc::CPU_STATE_IDLE as usize] as u64,
+            ..CpuTimes::default()
+        })
+        .collect::<Vec<CpuTimes>>();
+    unsafe {
+        libc::vm_deallocate(
+            libc::mach_task_self(),
+            info as libc::vm_address_t,
+            (info_count as usize * std::mem::size_of::<libc::integer_t>()) as libc::vm_size_t,
+        );
+    }
+    let total = cores.iter().fold(CpuTimes::default(), |acc, c| CpuTimes {
+        user: acc.user + c.user,
+        nice: acc.nice + c.nice,
+        system: acc.system + c.system,
+        idle: acc.idle + c.idle,
+        ..CpuTimes::default()
+    });
+    Ok(CpuStat { total, cores })
+}
@@ -1,12 +1,259 @@
+use super::format::Unit;
+use super::source::{single, unlabelled, FamilyDef, MetricDef, MetricSource, Samples, Selection};
+use super::{MetricError, Roots};
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+/// Load averages over the last 1, 5 and 15 minutes
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
 pub struct CPUMetrics {
+    /// Last minute
     pub m1: f32,
+    /// Last 5 minutes
     pub m5: f32,
+    /// Last 15 minutes
     pub m15: f32,
 }
 
+/// Cumulative time each CPU spent in every state since boot, in clock ticks
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+pub struct CpuTimes {
+    /// Running user processes
+    pub user: u64,
+    /// Running niced user processes
+    pub nice: u64,
+    /// Running the kernel
+    pub system: u64,
+    /// Nothing to do
+    pub idle: u64,
+    /// Idle while waiting for I/O
+    pub iowait: u64,
+    /// Handling hardware interrupts
+    pub irq: u64,
+    /// Handling software interrupts
+    pub softirq: u64,
+    /// Taken by the hypervisor for other guests
+    pub steal: u64,
+}
+
+impl CpuTimes {
+    fn total(&self) -> u64 {
+        self.user
+            + self.nice
+            + self.system
+            + self.idle
+            + self.iowait
+            + self.irq
+            + self.softirq
+            + self.steal
+    }
+
+    fn diff(&self, old: &CpuTimes) -> CpuTimes {
+        CpuTimes {
+            user: self.user.saturating_sub(old.user),
+            nice: self.nice.saturating_sub(old.nice),
+            system: self.system.saturating_sub(old.system),
+            idle: self.idle.saturating_sub(old.idle),
+            iowait: self.iowait.saturating_sub(old.iowait),
+            irq: self.irq.saturating_sub(old.irq),
+            softirq: self.softirq.saturating_sub(old.softirq),
+            steal: self.steal.saturating_sub(old.steal),
+        }
+    }
+
+    fn utilization(&self, old: &CpuTimes) -> CpuUtilization {
+        let d = self.diff(old);
+        let total = d.total();
+        let percent = |ticks: u64| {
+            if total == 0 {
+                0.0
+            } else {
+                ticks as f32 * 100.0 / total as f32
+            }
+        };
+        CpuUtilization {
+            busy: percent(total - d.idle - d.iowait),
+            user: percent(d.user + d.nice),
+            system: percent(d.system + d.irq + d.softirq),
+            iowait: percent(d.iowait),
+            steal: percent(d.steal),
+            cores: Vec::new(),
+        }
+    }
+}
+
+/// Tick counters for all CPUs combined and for every core
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CpuStat {
+    /// All CPUs combined
+    pub total: CpuTimes,
+    /// Every core, in the order the kernel lists them
+    pub cores: Vec<CpuTimes>,
+}
+
+impl CpuStat {
+    /// Utilization between the `old` sample and this one
+    pub fn diff(&self, old: &CpuStat) -> CpuUtilization {
+        // A sample that failed to collect would yield the average since boot
+        if old.total.total() == 0 {
+            return CpuUtilization::default();
+        }
+        let mut utilization = self.total.utilization(&old.total);
+        utilization.cores = self
+            .cores
+            .iter()
+            .zip(old.cores.iter())
+            .map(|(new, old)| new.utilization(old).busy)
+            .collect();
+        utilization
+    }
+}
+
+/// Percentage of time spent in each state between two samples
+#[derive(Debug, Clone, Default, Serialize, PartialEq)]
+pub struct CpuUtilization {
+    /// Neither idle nor waiting for I/O
+    pub busy: f32,
+    /// Includes niced processes
+    pub user: f32,
+    /// Includes interrupt handling
+    pub system: f32,
+    /// Idle while waiting for I/O
+    pub iowait: f32,
+    /// Taken by the hypervisor for other guests
+    pub steal: f32,
+    /// Busy percentage of every core
+    pub cores: Vec<f32>,
+}
+
+/// Load averages are gauges, the newest sample is the rate
+impl MetricSource for CPUMetrics {
+    type Rate = CPUMetrics;
+
+    const METRICS: &'static [MetricDef] = &[MetricDef {
+        check_field: Some(|field| match field {
+            "m1" | "m5" | "m15" => Ok(()),
+            _ => Err(format!("cpu field must be m1, m5 or m15, not '{}'", field)),
+        }),
+        ..MetricDef::single("cpu", "C", Unit::Number, |rate, _, field| {
+            single(f64::from(match field {
+                Some("m5") => rate.cpu.m5,
+                Some("m15") => rate.cpu.m15,
+                _ => rate.cpu.m1,
+            }))
+        })
+    }];
+
+    const FAMILIES: &'static [FamilyDef] = &[
+        FamilyDef::gauge("load1", "1 minute load average", |m| {
+            unlabelled(widen(m.cpu.m1))
+        }),
+        FamilyDef::gauge("load5", "5 minute load average", |m| {
+            unlabelled(widen(m.cpu.m5))
+        }),
+        FamilyDef::gauge("load15", "15 minute load average", |m| {
+            unlabelled(widen(m.cpu.m15))
+        }),
+    ];
+
+    fn collect(roots: &Roots, _: &Selection) -> Result<CPUMetrics, MetricError> {
+        Ok(get_cpu_metrics(roots)?)
+    }
+
+    fn diff(&self, _: &CPUMetrics, _: &Duration) -> CPUMetrics {
+        *self
+    }
+}
+
+impl MetricSource for CpuStat {
+    type Rate = CpuUtilization;
+
+    const METRICS: &'static [MetricDef] = &[
+        MetricDef::single("cpu-usage", "CPU", Unit::Percent, |rate, _, _| {
+            single(f64::from(rate.cpu_utilization.busy))
+        }),
+        MetricDef::single("cpu-user", "CU", Unit::Percent, |rate, _, _| {
+            single(f64::from(rate.cpu_utilization.user))
+        }),
+        MetricDef::single("cpu-system", "CS", Unit::Percent, |rate, _, _| {
+            single(f64::from(rate.cpu_utilization.system))
+        }),
+        MetricDef::single("cpu-iowait", "CW", Unit::Percent, |rate, _, _| {
+            single(f64::from(rate.cpu_utilization.iowait))
+        }),
+        MetricDef::single("cpu-steal", "CST", Unit::Percent, |rate, _, _| {
+            single(f64::from(rate.cpu_utilization.steal))
+        }),
+        MetricDef {
+            multiple: true,
+            check_field: Some(|field| {
+                field
+                    .parse::<usize>()
+                    .map(|_| ())
+                    .map_err(|_| format!("cpu-cores field must be a core index, not '{}'", field))
+            }),
+            ..MetricDef::single("cpu-cores", "CC", Unit::Percent, |rate, _, field| {
+                rate.cpu_utilization
+                    .cores
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, _)| field.is_none_or(|f| f == i.to_string()))
+                    .map(|(_, c)| (None, f64::from(*c)))
+                    .collect()
+            })
+        },
+    ];
+
+    const FAMILIES: &'static [FamilyDef] = &[FamilyDef::counter(
+        "cpu_seconds_total",
+        "Seconds each CPU spent in every mode",
+        |m| seconds(&m.cpu_stat, clock_ticks()),
+    )];
+
+    fn collect(roots: &Roots, _: &Selection) -> Result<CpuStat, MetricError> {
+        Ok(get_cpu_stat(roots)?)
+    }
+
+    fn diff(&self, old: &CpuStat, _: &Duration) -> CpuUtilization {
+        CpuStat::diff(self, old)
+    }
+}
+
+/// Widens without exposing the f32 rounding error, e.g. 0.35 instead of 0.3499999940395355
+fn widen(v: f32) -> f64 {
+    v.to_string().parse().unwrap_or(f64::NAN)
+}
+
+/// Clock ticks per second, the unit of the CPU times
+fn clock_ticks() -> f64 {
+    match unsafe { libc::sysconf(libc::_SC_CLK_TCK) } {
+        ticks if ticks > 0 => ticks as f64,
+        _ => 100.0,
+    }
+}
+
+/// Time every core spent in every mode, labelled by core index and mode
+fn seconds(stat: &CpuStat, ticks: f64) -> Samples {
+    let mut samples = Vec::new();
+    for (i, core) in stat.cores.iter().enumerate() {
+        let modes = [
+            ("user", core.user),
+            ("nice", core.nice),
+            ("system", core.system),
+            ("idle", core.idle),
+            ("iowait", core.iowait),
+            ("irq", core.irq),
+            ("softirq", core.softirq),
+            ("steal", core.steal),
+        ];
+        for (mode, value) in modes.iter() {
+            let labels = vec![("cpu", i.to_string()), ("mode", mode.to_string())];
+            samples.push((labels, *value as f64 / ticks));
+        }
+    }
+    samples
+}
+
 mod error;
 
 #[cfg(target_os = "macos")]
@@ -21,3 +268,61 @@ pub use linux::*;
 
 pub use error::CpuError;
 pub use get_cpu_metrics;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_utilization() {
+        let times = |user, system, idle, iowait| CpuTimes {
+            user,
+            system,
+            idle,
+            iowait,
+            ..CpuTimes::default()
+        };
+        let old = CpuStat {
+            total: times(100, 100, 100, 100),
+            cores: vec![times(50, 50, 50, 50), times(50, 50, 50, 50)],
+        };
+        let new = CpuStat {
+            total: times(150, 110, 130, 110),
+            cores: vec![times(100, 50, 50, 50), times(50, 60, 80, 60)],
+        };
+        let u = new.diff(&old);
+        assert_eq!(u.busy, 60.0);
+        assert_eq!(u.user, 50.0);
+        assert_eq!(u.system, 10.0);
+        assert_eq!(u.iowait, 10.0);
+        assert_eq!(u.steal, 0.0);
+        assert_eq!(u.cores, vec![100.0, 20.0]);
+    }
+
+    #[test]
+    fn test_utilization_without_history() {
+        let u = CpuStat::default().diff(&CpuStat::default());
+        assert_eq!(u, CpuUtilization::default());
+    }
+
+    #[test]
+    fn test_seconds() {
+        let stat = CpuStat {
+            cores: vec![
+                CpuTimes::default(),
+                CpuTimes {
+                    user: 250,
+                    idle: 1000,
+                    ..CpuTimes::default()
+                },
+            ],
+            ..CpuStat::default()
+        };
+        let samples = seconds(&stat, 100.0);
+        assert_eq!(samples.len(), 16);
+        let labels = vec![("cpu", "1".to_string()), ("mode", "user".to_string())];
+        assert_eq!(samples[8], (labels, 2.5));
+        assert_eq!(samples[11].1, 10.0);
+        assert_eq!(widen(0.35), 0.35);
+    }
+}
@@ -0,0 +1,293 @@
+use super::counter_delta;
+use super::format::Unit;
+use super::source::{single, FamilyDef, MetricDef, MetricSource, Samples, Selection};
+use super::{MetricError, MetricRate, Roots, TimeTaggedMetric};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Size of a sector as reported in /proc/diskstats, independent of the device
+#[cfg(target_os = "linux")]
+const SECTOR_SIZE: u64 = 512;
+
+/// Device name prefixes that do not correspond to physical disks
+#[cfg(target_os = "linux")]
+const VIRTUAL_PREFIXES: &[&str] = &["loop", "ram", "dm-", "zram"];
+
+/// Reading the block device counters failed
+#[derive(Debug)]
+pub enum DiskError {
+    /// Reading the counters file failed
+    IO(std::io::Error),
+    /// The counters could not be parsed
+    Parse(String),
+}
+
+impl std::fmt::Display for DiskError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DiskError::IO(e) => write!(f, "{}", &e),
+            DiskError::Parse(e) => write!(f, "{}", &e),
+        }
+    }
+}
+
+impl From<std::io::Error> for DiskError {
+    fn from(e: std::io::Error) -> DiskError {
+        DiskError::IO(e)
+    }
+}
+
+/// Cumulative counters of a block device since boot
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct DiskDeviceMetrics {
+    /// Bytes read
+    pub read_bytes: u64,
+    /// Bytes written
+    pub write_bytes: u64,
+    /// Whole physical disk, partitions and virtual devices are not counted in totals
+    pub physical: bool,
+}
+
+/// Byte counters of every block device
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct DiskMetrics {
+    /// Keyed by device name, e.g. `sda`
+    pub devices: BTreeMap<String, DiskDeviceMetrics>,
+}
+
+impl DiskMetrics {
+    /// Throughput of every device since the `old` sample, `dtime` earlier
+    pub fn diff(&self, old: &DiskMetrics, dtime: &Duration) -> DiskMetricRate {
+        let secs = dtime.as_secs_f64();
+        let devices = self
+            .devices
+            .iter()
+            .filter_map(|(name, new)| {
+                let old = old.devices.get(name)?;
+                let rate = DiskDeviceRate {
+                    read_rate: counter_delta(new.read_bytes, old.read_bytes)? as f64 / secs,
+                    write_rate: counter_delta(new.write_bytes, old.write_bytes)? as f64 / secs,
+                    physical: new.physical,
+                };
+                Some((name.clone(), rate))
+            })
+            .collect();
+        DiskMetricRate { devices }
+    }
+}
+
+impl MetricSource for DiskMetrics {
+    type Rate = DiskMetricRate;
+
+    const METRICS: &'static [MetricDef] = &[
+        MetricDef::single(
+            "disk-read",
+            "R",
+            Unit::BytesPerSecond,
+            |rate, selection, field| single(total(rate, selection, field).read_rate),
+        )
+        .with_any_field(),
+        MetricDef::single(
+            "disk-write",
+            "W",
+            Unit::BytesPerSecond,
+            |rate, selection, field| single(total(rate, selection, field).write_rate),
+        )
+        .with_any_field(),
+    ];
+
+    const FAMILIES: &'static [FamilyDef] = &[
+        FamilyDef::counter("disk_read_bytes_total", "Bytes read from the device", |m| {
+            per_device(m, |d| d.read_bytes)
+        }),
+        FamilyDef::counter(
+            "disk_written_bytes_total",
+            "Bytes written to the device",
+            |m| per_device(m, |d| d.write_bytes),
+        ),
+    ];
+
+    fn collect(roots: &Roots, _: &Selection) -> Result<DiskMetrics, MetricError> {
+        Ok(get_disk_metrics(roots)?)
+    }
+
+    fn diff(&self, old: &DiskMetrics, dtime: &Duration) -> DiskMetricRate {
+        DiskMetrics::diff(self, old, dtime)
+    }
+}
+
+/// Counter of every device in the sample, labelled by its name
+fn per_device(m: &TimeTaggedMetric, value: fn(&DiskDeviceMetrics) -> u64) -> Samples {
+    m.disk
+        .devices
+        .iter()
+        .map(|(name, d)| (vec![("device", name.clone())], value(d) as f64))
+        .collect()
+}
+
+/// The device named by the field or the selection, or all physical disks
+fn total(rate: &MetricRate, selection: &Selection, field: Option<&str>) -> DiskDeviceRate {
+    rate.disk.total(field.or(selection.device.as_deref()))
+}
+
+/// Throughput of a block device in bytes per second
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct DiskDeviceRate {
+    /// Bytes read per second
+    pub read_rate: f64,
+    /// Bytes written per second
+    pub write_rate: f64,
+    /// See `DiskDeviceMetrics::physical`
+    pub physical: bool,
+}
+
+/// Throughput of every block device
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct DiskMetricRate {
+    /// Keyed by device name
+    pub devices: BTreeMap<String, DiskDeviceRate>,
+}
+
+impl DiskMetricRate {
+    /// Exponentially weighted average of `self` and `sample`, see `NetworkMetricRate::smooth`
+    pub fn smooth(&self, sample: &DiskMetricRate, alpha: f64) -> DiskMetricRate {
+        let devices = sample
+            .devices
+            .iter()
+            .map(|(name, new)| {
+                let smoothed = match self.devices.get(name) {
+                    Some(old) => DiskDeviceRate {
+                        read_rate: old.read_rate + alpha * (new.read_rate - old.read_rate),
+                        write_rate: old.write_rate + alpha * (new.write_rate - old.write_rate),
+                        physical: new.physical,
+                    },
+                    None => *new,
+                };
+                (name.clone(), smoothed)
+            })
+            .collect();
+        DiskMetricRate { devices }
+    }
+
+    /// Rate of a single device, or the sum over all physical disks if `device` is `None`
+    pub fn total(&self, device: Option<&str>) -> DiskDeviceRate {
+        match device {
+            Some(device) => self.devices.get(device).copied().unwrap_or_default(),
+            None => self.devices.values().filter(|d| d.physical).fold(
+                DiskDeviceRate::default(),
+                |acc, d| DiskDeviceRate {
+                    read_rate: acc.read_rate + d.read_rate,
+                    write_rate: acc.write_rate + d.write_rate,
+                    physical: true,
+                },
+            ),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub fn get_disk_metrics(roots: &Roots) -> Result<DiskMetrics, DiskError> {
+    let proc = std::fs::read_to_string(roots.proc("diskstats"))?;
+    parse_linux_proc_diskstats(&proc, |name| {
+        roots
+            .sys("class/block")
+            .join(name)
+            .join("partition")
+            .exists()
+    })
+}
+
+/// Disk statistics are not collected on macOS yet
+#[cfg(target_os = "macos")]
+pub fn get_disk_metrics(_: &Roots) -> Result<DiskMetrics, DiskError> {
+    Ok(DiskMetrics::default())
+}
+
+#[cfg(target_os = "linux")]
+fn parse_linux_proc_diskstats<F>(s: &str, is_partition: F) -> Result<DiskMetrics, DiskError>
+where
+    F: Fn(&str) -> bool,
+{
+    let mut devices = BTreeMap::new();
+    for line in s.lines() {
+        let fields = line.split_whitespace().collect::<Vec<&str>>();
+        if fields.len() < 10 {
+            continue;
+        }
+        let name = fields[2];
+        let sectors = |i: usize| {
+            fields[i]
+                .parse::<u64>()
+                .map_err(|e| DiskError::Parse(format!("{}: {}", name, e)))
+        };
+        let read_bytes = sectors(5)? * SECTOR_SIZE;
+        let write_bytes = sectors(9)? * SECTOR_SIZE;
+        let physical = !VIRTUAL_PREFIXES.iter().any(|p| name.starts_with(p)) && !is_partition(name);
+        devices.insert(
+            name.to_string(),
+            DiskDeviceMetrics {
+                read_bytes,
+                write_bytes,
+                physical,
+            },
+        );
+    }
+    Ok(DiskMetrics { devices })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(target_os = "linux")]
+    static LINUX_PROC_DISKSTATS: &str = include_str!("test/root/proc/diskstats");
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_linux() {
+        let partitions = ["nvme0n1p1", "nvme0n1p2", "sda1"];
+        let m = super::parse_linux_proc_diskstats(LINUX_PROC_DISKSTATS, |name| {
+            partitions.contains(&name)
+        })
+        .ok()
+        .unwrap();
+        assert_eq!(m.devices.len(), 9);
+        let nvme = m.devices["nvme0n1"];
+        assert_eq!(nvme.read_bytes, 31549554 * 512);
+        assert_eq!(nvme.write_bytes, 72108258 * 512);
+        let physical = m
+            .devices
+            .iter()
+            .filter(|(_, d)| d.physical)
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<&str>>();
+        assert_eq!(physical, vec!["nvme0n1", "sda"]);
+    }
+
+    #[test]
+    fn test_diff() {
+        let device = |read_bytes, write_bytes, physical| DiskDeviceMetrics {
+            read_bytes,
+            write_bytes,
+            physical,
+        };
+        let mut old = DiskMetrics::default();
+        old.devices.insert("sda".to_string(), device(1000, 0, true));
+        old.devices
+            .insert("sda1".to_string(), device(1000, 0, false));
+        let mut new = DiskMetrics::default();
+        new.devices
+            .insert("sda".to_string(), device(3000, 4000, true));
+        new.devices
+            .insert("sda1".to_string(), device(3000, 4000, false));
+        new.devices
+            .insert("sdb".to_string(), device(9000, 9000, true));
+        let rate = new.diff(&old, &Duration::new(2, 0));
+        let total = rate.total(None);
+        assert_eq!(total.read_rate, 1000.0);
+        assert_eq!(total.write_rate, 2000.0);
+        assert_eq!(rate.total(Some("sda1")).write_rate, 2000.0);
+        assert_eq!(rate.total(Some("sdc")), DiskDeviceRate::default());
+    }
+}
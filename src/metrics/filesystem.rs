@@ -0,0 +1,344 @@
+use super::format::Unit;
+use super::source::{FamilyDef, MetricDef, MetricSource, Samples, Selection, Values};
+use super::{MetricError, MetricRate, Roots, TimeTaggedMetric};
+use serde::{Deserialize, Serialize};
+use std::ffi::CString;
+use std::os::unix::ffi::OsStringExt;
+use std::time::Duration;
+
+/// Filesystem types that do not store data on a disk, or that may block on the network
+#[cfg(target_os = "linux")]
+const SKIPPED_TYPES: &[&str] = &[
+    "autofs",
+    "binfmt_misc",
+    "bpf",
+    "cgroup",
+    "cgroup2",
+    "cifs",
+    "configfs",
+    "debugfs",
+    "devpts",
+    "devtmpfs",
+    "efivarfs",
+    "fuse.sshfs",
+    "fusectl",
+    "hugetlbfs",
+    "mqueue",
+    "nfs",
+    "nfs4",
+    "nsfs",
+    "proc",
+    "pstore",
+    "ramfs",
+    "rpc_pipefs",
+    "securityfs",
+    "selinuxfs",
+    "smb3",
+    "squashfs",
+    "sysfs",
+    "tmpfs",
+    "tracefs",
+];
+
+/// Listing or measuring the filesystems failed
+#[derive(Debug)]
+pub enum FilesystemError {
+    /// Reading the mount table or a filesystem's usage failed
+    IO(std::io::Error),
+}
+
+impl std::fmt::Display for FilesystemError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            FilesystemError::IO(e) => write!(f, "{}", &e),
+        }
+    }
+}
+
+impl From<std::io::Error> for FilesystemError {
+    fn from(e: std::io::Error) -> FilesystemError {
+        FilesystemError::IO(e)
+    }
+}
+
+/// Capacity of a mounted filesystem, all values are in bytes
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FilesystemUsage {
+    /// Where the filesystem is mounted
+    pub mount_point: String,
+    /// Size of the filesystem
+    pub total: u64,
+    /// Free space including reserved blocks
+    pub free: u64,
+    /// Free space usable by unprivileged users, excludes reserved blocks
+    pub available: u64,
+}
+
+impl FilesystemUsage {
+    /// Space taken by files
+    pub fn used(&self) -> u64 {
+        self.total.saturating_sub(self.free)
+    }
+
+    /// Percentage of the space available to users that is used, same as `df`
+    pub fn percent(&self) -> f64 {
+        let usable = self.used() + self.available;
+        if usable == 0 {
+            0.0
+        } else {
+            self.used() as f64 / usable as f64 * 100.0
+        }
+    }
+}
+
+/// Filesystem usage is a gauge, every value is labelled by its mount point
+impl MetricSource for Vec<FilesystemUsage> {
+    type Rate = Vec<FilesystemUsage>;
+
+    const METRICS: &'static [MetricDef] = &[
+        MetricDef {
+            name: "disk-usage",
+            unit: Unit::Percent,
+            values: |rate, selection, field| filesystems(rate, selection, field, |fs| fs.percent()),
+            ..FILESYSTEM_METRIC
+        },
+        MetricDef {
+            name: "disk-used",
+            unit: Unit::Bytes,
+            values: |rate, selection, field| {
+                filesystems(rate, selection, field, |fs| fs.used() as f64)
+            },
+            ..FILESYSTEM_METRIC
+        },
+        MetricDef {
+            name: "disk-free",
+            unit: Unit::Bytes,
+            values: |rate, selection, field| {
+                filesystems(rate, selection, field, |fs| fs.available as f64)
+            },
+            ..FILESYSTEM_METRIC
+        },
+    ];
+
+    const FAMILIES: &'static [FamilyDef] = &[
+        FamilyDef::gauge("filesystem_size_bytes", "Filesystem size", |m| {
+            per_mount(m, |fs| fs.total)
+        }),
+        FamilyDef::gauge(
+            "filesystem_free_bytes",
+            "Free space, including reserved blocks",
+            |m| per_mount(m, |fs| fs.free),
+        ),
+        FamilyDef::gauge(
+            "filesystem_avail_bytes",
+            "Space available to unprivileged users",
+            |m| per_mount(m, |fs| fs.available),
+        ),
+    ];
+
+    fn collect(roots: &Roots, selection: &Selection) -> Result<Vec<FilesystemUsage>, MetricError> {
+        Ok(get_filesystem_metrics(roots, &selection.mounts)?)
+    }
+
+    fn diff(&self, _: &Vec<FilesystemUsage>, _: &Duration) -> Vec<FilesystemUsage> {
+        self.clone()
+    }
+}
+
+const FILESYSTEM_METRIC: MetricDef = MetricDef {
+    name: "",
+    label: None,
+    unit: Unit::Bytes,
+    multiple: true,
+    check_field: Some(|_| Ok(())),
+    values: |_, _, _| Vec::new(),
+};
+
+/// Value of every filesystem in the sample, labelled by its mount point
+fn per_mount(m: &TimeTaggedMetric, value: fn(&FilesystemUsage) -> u64) -> Samples {
+    m.filesystems
+        .iter()
+        .map(|fs| {
+            (
+                vec![("mountpoint", fs.mount_point.clone())],
+                value(fs) as f64,
+            )
+        })
+        .collect()
+}
+
+/// `value` of the mount point named by the field, or of the selected ones
+fn filesystems(
+    rate: &MetricRate,
+    selection: &Selection,
+    field: Option<&str>,
+    value: fn(&FilesystemUsage) -> f64,
+) -> Values {
+    rate.filesystems
+        .iter()
+        .filter(|fs| match field {
+            Some(field) => fs.mount_point == field,
+            None => selection.mounts.is_empty() || selection.mounts.contains(&fs.mount_point),
+        })
+        .map(|fs| (Some(fs.mount_point.clone()), value(fs)))
+        .collect()
+}
+
+/// Usage of the filesystem mounted at `mount_point` on the host of `roots`
+pub fn statvfs(roots: &Roots, mount_point: &str) -> Result<FilesystemUsage, FilesystemError> {
+    let path = CString::new(roots.host(mount_point).into_os_string().into_vec())
+        .map_err(std::io::Error::from)?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(FilesystemError::IO(std::io::Error::last_os_error()));
+    }
+    let block = stat.f_frsize as u64;
+    Ok(FilesystemUsage {
+        mount_point: mount_point.to_string(),
+        total: stat.f_blocks as u64 * block,
+        free: stat.f_bfree as u64 * block,
+        available: stat.f_bavail as u64 * block,
+    })
+}
+
+/// Usage of the filesystems at `mounts`, of any type, or of all local, disk
+/// backed filesystems if none are given
+pub fn get_filesystem_metrics(
+    roots: &Roots,
+    mounts: &[String],
+) -> Result<Vec<FilesystemUsage>, FilesystemError> {
+    let listed;
+    let mounts = if mounts.is_empty() {
+        listed = mount_points(roots)?;
+        &listed
+    } else {
+        mounts
+    };
+    // A mount point that vanished between listing and stat is not an error, one
+    // that was given but is missing is reported by the metrics having no value
+    Ok(mounts
+        .iter()
+        .filter_map(|m| statvfs(roots, m).ok())
+        .collect())
+}
+
+/// The mounts of init, `self` would be the mount namespace of the reader, e.g.
+/// a container's rather than the host's whose procfs is mounted into it
+#[cfg(target_os = "linux")]
+fn mount_points(roots: &Roots) -> Result<Vec<String>, FilesystemError> {
+    let mounts = std::fs::read_to_string(roots.proc("1/mounts"))?;
+    Ok(parse_linux_proc_self_mounts(&mounts))
+}
+
+#[cfg(target_os = "macos")]
+fn mount_points(_: &Roots) -> Result<Vec<String>, FilesystemError> {
+    let mut buf: *mut libc::statfs = std::ptr::null_mut();
+    let count = unsafe { libc::getmntinfo(&mut buf, libc::MNT_NOWAIT) };
+    if count <= 0 {
+        return Err(FilesystemError::IO(std::io::Error::last_os_error()));
+    }
+    // The buffer is owned by libc and reused by subsequent calls
+    let mounts = unsafe { std::slice::from_raw_parts(buf, count as usize) };
+    Ok(mounts
+        .iter()
+        .filter(|m| m.f_flags & libc::MNT_LOCAL as u32 != 0)
+        .map(|m| {
+            let name = unsafe { std::ffi::CStr::from_ptr(m.f_mntonname.as_ptr()) };
+            name.to_string_lossy().into_owned()
+        })
+        .collect())
+}
+
+#[cfg(target_os = "linux")]
+fn parse_linux_proc_self_mounts(s: &str) -> Vec<String> {
+    let mut mount_points: Vec<String> = Vec::new();
+    for line in s.lines() {
+        let fields = line.split_whitespace().collect::<Vec<&str>>();
+        if fields.len() < 3 || SKIPPED_TYPES.contains(&fields[2]) {
+            continue;
+        }
+        let mount_point = unescape_mount_point(fields[1]);
+        if !mount_points.contains(&mount_point) {
+            mount_points.push(mount_point);
+        }
+    }
+    mount_points
+}
+
+/// Whitespace and backslashes in mount points are escaped as octal, e.g. `\040`
+#[cfg(target_os = "linux")]
+fn unescape_mount_point(s: &str) -> String {
+    let mut out = Vec::with_capacity(s.len());
+    let bytes = s.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        let octal = bytes
+            .get(i + 1..i + 4)
+            .and_then(|o| std::str::from_utf8(o).ok())
+            .and_then(|o| u8::from_str_radix(o, 8).ok());
+        match (bytes[i], octal) {
+            (b'\\', Some(c)) => {
+                out.push(c);
+                i += 4;
+            }
+            (c, _) => {
+                out.push(c);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(target_os = "linux")]
+    static LINUX_PROC_SELF_MOUNTS: &str = include_str!("test/root/proc/1/mounts");
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_linux() {
+        let m = super::parse_linux_proc_self_mounts(LINUX_PROC_SELF_MOUNTS);
+        assert_eq!(m, vec!["/", "/boot/efi", "/home", "/mnt/backup disk"]);
+    }
+
+    #[test]
+    fn test_percent() {
+        let usage = FilesystemUsage {
+            mount_point: "/".to_string(),
+            total: 1000,
+            free: 300,
+            available: 200,
+        };
+        assert_eq!(usage.used(), 700);
+        assert!((usage.percent() - 700.0 / 900.0 * 100.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_statvfs_root() {
+        let usage = statvfs(&Roots::default(), "/").expect("statvfs /");
+        assert_eq!(usage.mount_point, "/");
+        assert!(usage.total > 0);
+        assert!(usage.free <= usage.total);
+
+        let host = Roots {
+            host: "/nonexistent".into(),
+            ..Roots::default()
+        };
+        assert!(statvfs(&host, "/").is_err());
+    }
+
+    #[test]
+    fn test_configured_mounts() {
+        // Measured whatever their type, and whether or not they are in the mount table
+        let dir = std::env::temp_dir().to_string_lossy().into_owned();
+        let mounts = vec![dir.clone(), "/nonexistent".to_string()];
+        let usage = get_filesystem_metrics(&Roots::default(), &mounts).expect("measured");
+        assert_eq!(
+            usage.iter().map(|fs| &fs.mount_point).collect::<Vec<_>>(),
+            vec![&dir]
+        );
+    }
+}
@@ -0,0 +1,277 @@
+//! Formatting of metric values for the status line
+
+use human_format::{Formatter, Scales};
+use serde::Serialize;
+
+/// Binary scaled bytes with two decimals, e.g. `1.50 kB`
+pub fn make_formatter() -> Formatter {
+    let suffixes = vec![" ", "k", "M", "G", "T"];
+    let mut scales = Scales::new();
+    scales.with_base(1024).with_suffixes(suffixes);
+    let mut f = Formatter::new();
+    f.with_scales(scales);
+    f.with_units("B");
+    f.with_decimals(2);
+    f
+}
+
+/// Bytes per second, right aligned to a constant width
+pub fn format_bytes(formatter: &Formatter, bytes: f64) -> String {
+    format!("{:>10}/s", formatter.format(bytes))
+}
+
+/// Events per second, scaled with SI suffixes
+pub fn format_per_second(value: f64) -> String {
+    let suffixes = vec![" ", "k", "M", "G"];
+    let mut scales = Scales::new();
+    scales.with_base(1000).with_suffixes(suffixes);
+    let mut f = Formatter::new();
+    f.with_scales(scales);
+    f.with_separator("");
+    f.with_decimals(1);
+    format!("{:>7}/s", f.format(value))
+}
+
+/// Bytes, right aligned to a constant width
+pub fn format_size(formatter: &Formatter, bytes: f64) -> String {
+    format!("{:>10}", formatter.format(bytes))
+}
+
+/// What a metric value measures, decides how it is formatted
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Unit {
+    /// Amount of memory or disk space
+    Bytes,
+    /// Network or disk throughput
+    BytesPerSecond,
+    /// Packets, errors or other events per second
+    PerSecond,
+    /// Between 0 and 100
+    Percent,
+    /// Plain number, e.g. load average
+    Number,
+}
+
+impl Unit {
+    /// Base of the scale suffixes, `None` if the value is not scaled
+    fn scale_base(&self) -> Option<f64> {
+        match self {
+            Unit::Bytes | Unit::BytesPerSecond => Some(1024.0),
+            Unit::PerSecond => Some(1000.0),
+            Unit::Percent | Unit::Number => None,
+        }
+    }
+
+    /// Multiplier of a scale suffix, `None` if the unit is not scaled or the suffix is unknown
+    pub fn scale_factor(&self, scale: char) -> Option<f64> {
+        let exponent = SCALES.iter().position(|s| *s == scale)?;
+        Some(self.scale_base()?.powi(exponent as i32))
+    }
+
+    /// Printed after a formatted value, empty for plain numbers
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            Unit::Bytes => "B",
+            Unit::BytesPerSecond => "B/s",
+            Unit::PerSecond => "/s",
+            Unit::Percent => "%",
+            Unit::Number => "",
+        }
+    }
+}
+
+const SCALES: &[char] = &['B', 'k', 'M', 'G', 'T'];
+
+/// Formatting options of a value, `[align][width][.precision][scale]`,
+/// e.g. `>8.1M` right aligns to 8 characters with one decimal in megabytes
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Spec {
+    /// `<`, `^` or `>`, right aligned if not set
+    pub align: Option<char>,
+    /// Minimum number of characters
+    pub width: Option<usize>,
+    /// Number of decimals
+    pub precision: Option<usize>,
+    /// One of `B`, `k`, `M`, `G`, `T`, automatic if not set
+    pub scale: Option<char>,
+}
+
+impl std::str::FromStr for Spec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Spec, String> {
+        let mut spec = Spec::default();
+        let mut rest = s;
+        if let Some(c) = rest.chars().next().filter(|c| "<^>".contains(*c)) {
+            spec.align = Some(c);
+            rest = &rest[1..];
+        }
+        let digits = |s: &str| s.chars().take_while(char::is_ascii_digit).count();
+        let n = digits(rest);
+        if n > 0 {
+            spec.width = rest[..n].parse().ok();
+            rest = &rest[n..];
+        }
+        if let Some(after_dot) = rest.strip_prefix('.') {
+            let n = digits(after_dot);
+            if n == 0 {
+                return Err(format!("missing precision in '{}'", s));
+            }
+            spec.precision = after_dot[..n].parse().ok();
+            rest = &after_dot[n..];
+        }
+        let mut chars = rest.chars();
+        match (chars.next(), chars.next()) {
+            (None, _) => {}
+            (Some(c), None) if SCALES.contains(&c) => spec.scale = Some(c),
+            _ => return Err(format!("invalid format spec '{}'", s)),
+        }
+        Ok(spec)
+    }
+}
+
+impl Spec {
+    /// Whether the spec can format values of `unit`
+    pub fn check(&self, unit: Unit) -> Result<(), String> {
+        match (self.scale, unit.scale_base()) {
+            (Some(scale), None) => Err(format!("scale '{}' does not apply to {:?}", scale, unit)),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Formats with the fixed width defaults unless `spec` asks otherwise
+pub fn format_value(value: f64, unit: Unit, spec: &Spec) -> String {
+    if *spec == Spec::default() {
+        return match unit {
+            Unit::Bytes => format_size(&make_formatter(), value),
+            Unit::BytesPerSecond => format_bytes(&make_formatter(), value),
+            Unit::PerSecond => format_per_second(value),
+            Unit::Percent => format!("{:5.1}%", value),
+            Unit::Number => format!("{:.2}", value),
+        };
+    }
+    let text = match unit.scale_base() {
+        Some(base) => {
+            let exponent = match spec.scale {
+                Some(scale) => SCALES.iter().position(|s| *s == scale).unwrap_or(0),
+                None => {
+                    let mut exponent = 0;
+                    while value.abs() >= base.powi(exponent as i32 + 1)
+                        && exponent + 1 < SCALES.len()
+                    {
+                        exponent += 1;
+                    }
+                    exponent
+                }
+            };
+            let prefix = if exponent == 0 {
+                String::new()
+            } else {
+                SCALES[exponent].to_string()
+            };
+            format!(
+                "{:.*}{}{}",
+                spec.precision.unwrap_or(2),
+                value / base.powi(exponent as i32),
+                prefix,
+                unit.suffix()
+            )
+        }
+        None => {
+            let precision = match unit {
+                Unit::Percent => 1,
+                _ => 2,
+            };
+            format!(
+                "{:.*}{}",
+                spec.precision.unwrap_or(precision),
+                value,
+                unit.suffix()
+            )
+        }
+    };
+    let width = spec.width.unwrap_or(0);
+    match spec.align {
+        Some('<') => format!("{:<1$}", text, width),
+        Some('^') => format!("{:^1$}", text, width),
+        _ => format!("{:>1$}", text, width),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_formatter() {
+        let f = make_formatter();
+        assert_eq!(format_bytes(&f, 1.0), "   1.00  B/s");
+        assert_eq!(format_bytes(&f, 10.0), "  10.00  B/s");
+        assert_eq!(format_bytes(&f, 100.0), " 100.00  B/s");
+        assert_eq!(format_bytes(&f, 1000.0), "1000.00  B/s");
+        assert_eq!(format_bytes(&f, 10_000.0), "   9.77 kB/s");
+        assert_eq!(format_bytes(&f, 1024.0 * 1024.0), "   1.00 MB/s");
+        assert_eq!(format_bytes(&f, 1024.0 * 1024.0 * 1024.0), "   1.00 GB/s");
+    }
+
+    #[test]
+    fn test_per_second_formatter() {
+        assert_eq!(format_per_second(0.0), "   0.0 /s");
+        assert_eq!(format_per_second(12.34), "  12.3 /s");
+        assert_eq!(format_per_second(45_600.0), "  45.6k/s");
+    }
+
+    #[test]
+    fn test_size_formatter() {
+        let f = make_formatter();
+        assert_eq!(format_size(&f, 512.0), " 512.00  B");
+        assert_eq!(format_size(&f, 16_314_244.0 * 1024.0), "  15.56 GB");
+    }
+
+    #[test]
+    fn test_spec_parse() {
+        let spec: Spec = ">8.1M".parse().expect("valid spec");
+        assert_eq!(
+            spec,
+            Spec {
+                align: Some('>'),
+                width: Some(8),
+                precision: Some(1),
+                scale: Some('M'),
+            }
+        );
+        assert_eq!(".0".parse::<Spec>().map(|s| s.precision), Ok(Some(0)));
+        assert!("8.".parse::<Spec>().is_err());
+        assert!("x".parse::<Spec>().is_err());
+        assert!(Spec::default().check(Unit::Percent).is_ok());
+        assert!("M".parse::<Spec>().unwrap().check(Unit::Percent).is_err());
+    }
+
+    #[test]
+    fn test_format_value() {
+        let spec = |s: &str| s.parse::<Spec>().unwrap();
+        let rate = 10_000.0;
+        assert_eq!(
+            format_value(rate, Unit::BytesPerSecond, &Spec::default()),
+            "   9.77 kB/s"
+        );
+        assert_eq!(
+            format_value(rate, Unit::BytesPerSecond, &spec(".1")),
+            "9.8kB/s"
+        );
+        assert_eq!(
+            format_value(rate, Unit::BytesPerSecond, &spec(".0B")),
+            "10000B/s"
+        );
+        assert_eq!(
+            format_value(rate, Unit::BytesPerSecond, &spec("<9.1k")),
+            "9.8kB/s  "
+        );
+        assert_eq!(format_value(rate, Unit::PerSecond, &spec(".1")), "10.0k/s");
+        assert_eq!(format_value(0.356, Unit::Number, &Spec::default()), "0.36");
+        assert_eq!(format_value(0.356, Unit::Number, &spec(".1")), "0.4");
+        assert_eq!(format_value(42.26, Unit::Percent, &spec("6")), " 42.3%");
+    }
+}
@@ -0,0 +1,244 @@
+use super::format::Unit;
+use super::source::{single, unlabelled, FamilyDef, MetricDef, MetricSource, Selection};
+use super::{MetricError, Roots};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+#[cfg(target_os = "macos")]
+use sysctl::Sysctl;
+
+/// Reading the memory usage failed
+#[derive(Debug)]
+pub enum MemoryError {
+    /// A sysctl value could not be read
+    CtlError,
+    /// The memory statistics could not be read or parsed
+    GetMetrics(String),
+    /// Reading the meminfo file failed
+    IO(std::io::Error),
+}
+
+impl std::fmt::Display for MemoryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            MemoryError::CtlError => write!(f, "CtlError"),
+            MemoryError::GetMetrics(e) => write!(f, "{}", &e),
+            MemoryError::IO(e) => write!(f, "{}", &e),
+        }
+    }
+}
+
+impl From<std::io::Error> for MemoryError {
+    fn from(e: std::io::Error) -> MemoryError {
+        MemoryError::IO(e)
+    }
+}
+
+/// Memory usage snapshot, all values are in bytes
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy)]
+pub struct MemoryMetrics {
+    /// Installed memory usable by the kernel
+    pub total: u64,
+    /// Not used for anything, not even caches
+    pub free: u64,
+    /// Free or reclaimable without swapping
+    pub available: u64,
+    /// Block device buffers
+    pub buffers: u64,
+    /// Page cache
+    pub cached: u64,
+    /// Size of all swap areas
+    pub swap_total: u64,
+    /// Unused swap space
+    pub swap_free: u64,
+}
+
+impl MemoryMetrics {
+    /// Memory that can not be reclaimed without swapping
+    pub fn used(&self) -> u64 {
+        self.total.saturating_sub(self.available)
+    }
+
+    /// Swap space in use
+    pub fn swap_used(&self) -> u64 {
+        self.swap_total.saturating_sub(self.swap_free)
+    }
+}
+
+/// Memory usage is a gauge, the newest sample is the rate
+impl MetricSource for MemoryMetrics {
+    type Rate = MemoryMetrics;
+
+    const METRICS: &'static [MetricDef] = &[
+        MetricDef::single("memory", "M", Unit::Bytes, |rate, _, _| {
+            single(rate.memory.used() as f64)
+        }),
+        MetricDef::single("memory-available", "MA", Unit::Bytes, |rate, _, _| {
+            single(rate.memory.available as f64)
+        }),
+        MetricDef::single("memory-cached", "MC", Unit::Bytes, |rate, _, _| {
+            single(rate.memory.cached as f64)
+        }),
+        MetricDef::single("memory-buffers", "MB", Unit::Bytes, |rate, _, _| {
+            single(rate.memory.buffers as f64)
+        }),
+        MetricDef::single("swap", "S", Unit::Bytes, |rate, _, _| {
+            single(rate.memory.swap_used() as f64)
+        }),
+    ];
+
+    const FAMILIES: &'static [FamilyDef] = &[
+        FamilyDef::gauge("memory_total_bytes", "Total memory", |m| {
+            unlabelled(m.memory.total as f64)
+        }),
+        FamilyDef::gauge("memory_free_bytes", "Unused memory", |m| {
+            unlabelled(m.memory.free as f64)
+        }),
+        FamilyDef::gauge(
+            "memory_available_bytes",
+            "Memory available without swapping",
+            |m| unlabelled(m.memory.available as f64),
+        ),
+        FamilyDef::gauge("memory_buffers_bytes", "Memory used by buffers", |m| {
+            unlabelled(m.memory.buffers as f64)
+        }),
+        FamilyDef::gauge(
+            "memory_cached_bytes",
+            "Memory used by the page cache",
+            |m| unlabelled(m.memory.cached as f64),
+        ),
+        FamilyDef::gauge("swap_total_bytes", "Total swap", |m| {
+            unlabelled(m.memory.swap_total as f64)
+        }),
+        FamilyDef::gauge("swap_free_bytes", "Unused swap", |m| {
+            unlabelled(m.memory.swap_free as f64)
+        }),
+    ];
+
+    fn collect(roots: &Roots, _: &Selection) -> Result<MemoryMetrics, MetricError> {
+        Ok(get_memory_metrics(roots)?)
+    }
+
+    fn diff(&self, _: &MemoryMetrics, _: &Duration) -> MemoryMetrics {
+        *self
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub fn get_memory_metrics(roots: &Roots) -> Result<MemoryMetrics, MemoryError> {
+    let proc = std::fs::read_to_string(roots.proc("meminfo"))?;
+    parse_linux_proc_meminfo(&proc)
+}
+
+#[cfg(target_os = "linux")]
+fn parse_linux_proc_meminfo(s: &str) -> Result<MemoryMetrics, MemoryError> {
+    let mut m = MemoryMetrics::default();
+    for line in s.lines() {
+        let mut parts = line.split_whitespace();
+        let (key, value) = match (parts.next(), parts.next()) {
+            (Some(key), Some(value)) => (key, value),
+            _ => continue,
+        };
+        // Values are reported in kB regardless of the unit column
+        let bytes = match value.parse::<u64>() {
+            Ok(kb) => kb * 1024,
+            Err(_) => continue,
+        };
+        match key {
+            "MemTotal:" => m.total = bytes,
+            "MemFree:" => m.free = bytes,
+            "MemAvailable:" => m.available = bytes,
+            "Buffers:" => m.buffers = bytes,
+            "Cached:" => m.cached = bytes,
+            "SwapTotal:" => m.swap_total = bytes,
+            "SwapFree:" => m.swap_free = bytes,
+            _ => {}
+        }
+    }
+    if m.total == 0 {
+        return Err(MemoryError::GetMetrics(
+            "MemTotal missing from /proc/meminfo".to_string(),
+        ));
+    }
+    Ok(m)
+}
+
+#[repr(C)]
+#[cfg(target_os = "macos")]
+struct xsw_usage {
+    xsu_total: u64,
+    xsu_avail: u64,
+    xsu_used: u64,
+    xsu_pagesize: u32,
+    xsu_encrypted: bool,
+}
+
+#[cfg(target_os = "macos")]
+fn ctl_u64(name: &str) -> Result<u64, MemoryError> {
+    let ctl = sysctl::Ctl::new(name).map_err(|_| MemoryError::CtlError)?;
+    match ctl.value().map_err(|_| MemoryError::CtlError)? {
+        sysctl::CtlValue::S64(v) | sysctl::CtlValue::Long(v) => Ok(v as u64),
+        sysctl::CtlValue::U64(v) | sysctl::CtlValue::Ulong(v) => Ok(v),
+        sysctl::CtlValue::Int(v) | sysctl::CtlValue::S32(v) => Ok(v as u64),
+        sysctl::CtlValue::Uint(v) | sysctl::CtlValue::U32(v) => Ok(v as u64),
+        _ => Err(MemoryError::GetMetrics(format!(
+            "value retrieved from {} was not a number",
+            name
+        ))),
+    }
+}
+
+/// macOS does not expose buffers and cache the way Linux does, so only
+/// the free page count is used as available memory
+#[cfg(target_os = "macos")]
+pub fn get_memory_metrics(_: &Roots) -> Result<MemoryMetrics, MemoryError> {
+    let total = ctl_u64("hw.memsize")?;
+    let page_size = ctl_u64("hw.pagesize")?;
+    let free = ctl_u64("vm.page_free_count")? * page_size;
+    let ctl = sysctl::Ctl::new("vm.swapusage").map_err(|_| MemoryError::CtlError)?;
+    let swap = match ctl.value().map_err(|_| MemoryError::CtlError)? {
+        sysctl::CtlValue::Struct(sval) => unsafe {
+            std::mem::transmute_copy::<u8, xsw_usage>(&sval[0])
+        },
+        _ => {
+            return Err(MemoryError::GetMetrics(
+                "value retrieved from ctl was not a struct".to_string(),
+            ))
+        }
+    };
+    Ok(MemoryMetrics {
+        total,
+        free,
+        available: free,
+        buffers: 0,
+        cached: 0,
+        swap_total: swap.xsu_total,
+        swap_free: swap.xsu_avail,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(target_os = "linux")]
+    static LINUX_PROC_MEMINFO: &str = include_str!("test/root/proc/meminfo");
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_linux() {
+        let m = super::parse_linux_proc_meminfo(LINUX_PROC_MEMINFO)
+            .ok()
+            .unwrap();
+        assert_eq!(m.total, 16314244 * 1024);
+        assert_eq!(m.free, 1735964 * 1024);
+        assert_eq!(m.available, 9811280 * 1024);
+        assert_eq!(m.buffers, 412916 * 1024);
+        assert_eq!(m.cached, 7654560 * 1024);
+        assert_eq!(m.used(), (16314244 - 9811280) * 1024);
+        assert_eq!(m.swap_used(), (2097148 - 1835004) * 1024);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_linux_missing_total() {
+        assert!(super::parse_linux_proc_meminfo("MemFree: 10 kB\n").is_err());
+    }
+}
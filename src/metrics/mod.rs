@@ -1,16 +1,48 @@
-use crate::metrics::network::get_network_metrics;
-pub use crate::metrics::network::NetworkMetrics;
-use cpu::get_cpu_metrics;
-use cpu::CPUMetrics;
+//! Sampling of system metrics and the rates between samples.
+//!
+//! Every kind of sample is collected by a [`MetricSource`] listed in the
+//! `sources!` registry below. A [`TimeTaggedMetric`] holds one sample of each,
+//! [`Metrics`] a history of them that computes a [`MetricRate`].
+
+pub use crate::metrics::network::{
+    InterfaceFilter, InterfaceMetrics, InterfaceRate, NetworkError, NetworkMetricRate,
+    NetworkMetrics,
+};
+pub use clock::Clocks;
+pub use cpu::{CPUMetrics, CpuError, CpuStat, CpuTimes, CpuUtilization};
+pub use disk::{DiskDeviceMetrics, DiskDeviceRate, DiskError, DiskMetricRate, DiskMetrics};
+pub use filesystem::{FilesystemError, FilesystemUsage};
+pub use memory::{MemoryError, MemoryMetrics};
+pub use roots::Roots;
 use serde::{Deserialize, Serialize};
+use smoothing::Ewma;
+pub use source::{FamilyDef, FamilyKind, MetricDef, MetricSource, Samples, Selection, Values};
 
+mod clock;
 mod cpu;
+mod disk;
+mod filesystem;
+pub mod format;
+mod memory;
 mod network;
+mod roots;
+mod smoothing;
+mod source;
 
+/// A source failed to collect its sample
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum MetricError {
+    /// From the network interface counters
     NetworkError(network::NetworkError),
+    /// From the load averages or CPU times
     CpuError(cpu::CpuError),
+    /// From the memory usage
+    MemoryError(memory::MemoryError),
+    /// From the block device counters
+    DiskError(disk::DiskError),
+    /// From the mounted filesystems
+    FilesystemError(filesystem::FilesystemError),
 }
 
 impl std::fmt::Display for MetricError {
@@ -18,89 +50,763 @@ impl std::fmt::Display for MetricError {
         match self {
             MetricError::NetworkError(e) => e.fmt(f),
             MetricError::CpuError(e) => e.fmt(f),
+            MetricError::MemoryError(e) => e.fmt(f),
+            MetricError::DiskError(e) => e.fmt(f),
+            MetricError::FilesystemError(e) => e.fmt(f),
+        }
+    }
+}
+
+/// Why a rate can not be computed from the samples at hand
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RateError {
+    /// Fewer than two samples
+    NotEnoughData,
+    /// The samples are too close together for a meaningful rate
+    WindowTooShort,
+    /// The newest sample is from the future, the clock was set back
+    ClockSkew,
+}
+
+impl std::fmt::Display for RateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RateError::NotEnoughData => write!(f, "Not enough data"),
+            RateError::WindowTooShort => write!(f, "Samples are too close together"),
+            RateError::ClockSkew => write!(f, "The clock was set back"),
         }
     }
 }
 
+impl std::error::Error for RateError {}
+
+impl std::error::Error for MetricError {}
+
 impl From<cpu::CpuError> for MetricError {
     fn from(e: cpu::CpuError) -> MetricError {
         MetricError::CpuError(e)
     }
 }
 
+impl From<memory::MemoryError> for MetricError {
+    fn from(e: memory::MemoryError) -> MetricError {
+        MetricError::MemoryError(e)
+    }
+}
+
+impl From<disk::DiskError> for MetricError {
+    fn from(e: disk::DiskError) -> MetricError {
+        MetricError::DiskError(e)
+    }
+}
+
+impl From<filesystem::FilesystemError> for MetricError {
+    fn from(e: filesystem::FilesystemError) -> MetricError {
+        MetricError::FilesystemError(e)
+    }
+}
+
 impl From<network::NetworkError> for MetricError {
     fn from(e: network::NetworkError) -> MetricError {
         MetricError::NetworkError(e)
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct TimeTaggedMetric {
-    time: std::time::Duration,
-    // TODO: Replace NetworkMetric by some trait
-    network: NetworkMetrics,
-    cpu: CPUMetrics,
+/// Declares every source of metrics as `sample_field => rate_field: Type`.
+///
+/// Generates their fields in `TimeTaggedMetric` and `MetricRate`, collecting
+/// and diffing them, the list of metrics offered on the command line and the
+/// families served by the exporter. The field names are part of the storage
+/// and JSON formats.
+macro_rules! sources {
+    ($($(#[$doc:meta])* $sample:ident => $rate:ident: $source:ty,)*) => {
+        /// One sample of every source
+        #[derive(Serialize, Deserialize, Debug, Default)]
+        pub struct TimeTaggedMetric {
+            /// When the sample was taken, since the Unix epoch
+            pub time: std::time::Duration,
+            $(
+                $(#[$doc])*
+                #[serde(default)]
+                pub $sample: $source,
+            )*
+            /// Identifies the boot the sample was taken in, counters restart with every boot
+            #[serde(default)]
+            pub boot_id: Option<String>,
+            /// Unlike `time` these are not changed by NTP or by hand
+            #[serde(default)]
+            pub clocks: Option<Clocks>,
+            /// Sources that failed to collect, by field name, they are left at their default
+            #[serde(default, skip_serializing_if = "Vec::is_empty")]
+            pub failed: Vec<String>,
+        }
+
+        /// Rates of every source between two samples
+        #[derive(Serialize, Debug, Default)]
+        pub struct MetricRate {
+            /// When the newest sample was taken, since the Unix epoch
+            #[serde(serialize_with = "serialize_secs")]
+            pub time: std::time::Duration,
+            /// Time between the samples the rates are computed from
+            #[serde(serialize_with = "serialize_secs")]
+            pub window: std::time::Duration,
+            $(
+                $(#[$doc])*
+                pub $rate: <$source as MetricSource>::Rate,
+            )*
+            /// Sources that failed to collect in either sample, by field name,
+            /// their rates are meaningless
+            #[serde(skip_serializing_if = "Vec::is_empty")]
+            pub failed: Vec<String>,
+        }
+
+        impl MetricRate {
+            /// Whether the source of `def` was collected in both samples
+            pub fn available(&self, def: &MetricDef) -> bool {
+                $(
+                    if <$source as MetricSource>::METRICS.contains(def) {
+                        return self.collected(stringify!($rate));
+                    }
+                )*
+                true
+            }
+        }
+
+        impl TimeTaggedMetric {
+            /// Families served by the exporter of every source that was
+            /// collected, in the order they are declared
+            pub fn families(&self) -> Vec<&'static FamilyDef> {
+                let mut families = Vec::new();
+                $(
+                    if self.collected(stringify!($sample)) {
+                        families.extend(<$source as MetricSource>::FAMILIES);
+                    }
+                )*
+                families
+            }
+        }
+
+        /// Number of registered sources
+        const SOURCES: usize = [$(stringify!($sample)),*].len();
+
+        /// A source that fails is left at its default and marked as failed
+        /// rather than losing the whole sample
+        fn collect(
+            time: std::time::Duration,
+            roots: &Roots,
+            selection: &Selection,
+        ) -> (TimeTaggedMetric, Failures) {
+            let mut failures = Vec::new();
+            let sample = TimeTaggedMetric {
+                time,
+                $($sample: <$source as MetricSource>::collect(roots, selection).unwrap_or_else(|e| {
+                    failures.push((stringify!($sample), e));
+                    Default::default()
+                }),)*
+                boot_id: get_boot_id(roots),
+                clocks: Clocks::now(),
+                failed: failures.iter().map(|(source, _)| source.to_string()).collect(),
+            };
+            (sample, failures)
+        }
+
+        fn rate_between(new: &TimeTaggedMetric, old: &TimeTaggedMetric) -> MetricRate {
+            let dtime = new.elapsed_since(old).unwrap_or_default();
+            let mut failed = Vec::new();
+            $(
+                if !new.collected(stringify!($sample)) || !old.collected(stringify!($sample)) {
+                    failed.push(stringify!($rate).to_string());
+                }
+            )*
+            MetricRate {
+                time: new.time,
+                window: dtime,
+                $($rate: MetricSource::diff(&new.$sample, &old.$sample, &dtime),)*
+                failed,
+            }
+        }
+
+        /// Collects every source on its own to tell which of them fail, by name
+        pub fn check_sources(
+            roots: &Roots,
+            selection: &Selection,
+        ) -> Vec<(&'static str, Result<(), MetricError>)> {
+            vec![$((
+                stringify!($sample),
+                <$source as MetricSource>::collect(roots, selection).map(|_| ()),
+            ),)*]
+        }
+
+        /// Every metric of every source, in the order they are declared
+        pub fn metric_defs() -> impl Iterator<Item = &'static MetricDef> {
+            std::iter::empty()$(.chain(<$source as MetricSource>::METRICS))*
+        }
+    };
+}
+
+sources! {
+    /// Per interface network traffic
+    network => network: NetworkMetrics,
+    /// Load averages
+    cpu => cpu: CPUMetrics,
+    /// Time spent in each CPU state
+    cpu_stat => cpu_utilization: CpuStat,
+    /// Memory and swap usage
+    memory => memory: MemoryMetrics,
+    /// Block device throughput
+    disk => disk: DiskMetrics,
+    /// Capacity of local filesystems
+    filesystems => filesystems: Vec<FilesystemUsage>,
+}
+
+impl TimeTaggedMetric {
+    /// Time between the samples, `None` if `older` is not older. Measured
+    /// by the monotonic clock when both samples have it.
+    pub fn elapsed_since(&self, older: &TimeTaggedMetric) -> Option<std::time::Duration> {
+        match (&self.clocks, &older.clocks) {
+            (Some(new), Some(old)) => new.elapsed_since(old),
+            _ => self.time.checked_sub(older.time),
+        }
+    }
+
+    /// Whether the source with the field name `source` was collected
+    pub fn collected(&self, source: &str) -> bool {
+        !self.failed.iter().any(|failed| failed == source)
+    }
+
+    /// Whether the system was suspended for long between the samples
+    fn suspended_since(&self, older: &TimeTaggedMetric) -> bool {
+        match (&self.clocks, &older.clocks) {
+            (Some(new), Some(old)) => new.suspended_since(old) > MAX_SUSPEND,
+            _ => false,
+        }
+    }
+}
+
+/// Increase of a counter between two samples, `None` if it was reset.
+///
+/// The counters read are 64 bits wide, so any decrease is a reset, e.g. a
+/// reloaded driver or a re-plugged device, rather than a wrap.
+pub fn counter_delta(new: u64, old: u64) -> Option<u64> {
+    new.checked_sub(old)
 }
 
+/// Rates span at most this many of the newest samples unless a window is given
+const RATE_SAMPLES: usize = 3;
+
+/// Samples a second apart or less are too noisy for a rate
+const MIN_RATE_WINDOW: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Tolerated difference between the clocks of processes sharing the storage
+const MAX_CLOCK_SKEW: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Samples across a longer suspend are not compared, the rate would average over the sleep
+const MAX_SUSPEND: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Moving averages kept for different half-lives, the least recently requested are dropped
+const MAX_SMOOTHED: usize = 4;
+
+/// How a rate is computed from the samples, all durations are in seconds
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct Averaging {
+    /// See `Metrics::get_rate`
+    #[serde(default)]
+    pub window: Option<u64>,
+    /// Half-life of the moving average of the network and disk rates
+    #[serde(default)]
+    pub half_life: Option<u64>,
+}
+
+impl MetricRate {
+    /// Whether the source with the field name `source` was collected in both samples
+    pub fn collected(&self, source: &str) -> bool {
+        !self.failed.iter().any(|failed| failed == source)
+    }
+}
+
+impl Averaging {
+    /// See `Metrics::get_rate`
+    pub fn window(&self) -> Option<std::time::Duration> {
+        self.window.map(|secs| std::time::Duration::new(secs, 0))
+    }
+
+    /// See `Metrics::smooth`
+    pub fn half_life(&self) -> Option<std::time::Duration> {
+        self.half_life.map(|secs| std::time::Duration::new(secs, 0))
+    }
+}
+
+/// History of samples, newest first, and the moving averages of their rates
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Metrics {
     metrics: Vec<TimeTaggedMetric>,
+    #[serde(default)]
+    smoothed: Vec<Ewma>,
+    /// Where the samples were read from, samples of other roots are not comparable
+    #[serde(default)]
+    roots: Roots,
 }
 
 impl Metrics {
+    /// History of a single sample read from the default roots
     pub fn new(metric: TimeTaggedMetric) -> Metrics {
+        Metrics::from_roots(metric, Roots::default())
+    }
+
+    /// History of a single sample read from `roots`
+    pub fn from_roots(metric: TimeTaggedMetric, roots: Roots) -> Metrics {
         Metrics {
             metrics: vec![metric],
+            smoothed: Vec::new(),
+            roots,
         }
     }
 
-    pub fn merge(self, other: Metrics) -> Metrics {
+    /// Most recent sample
+    pub fn latest(&self) -> Option<&TimeTaggedMetric> {
+        self.metrics.first()
+    }
+
+    /// Combines both histories, keeping the newest `capacity` samples. If they
+    /// were read from different roots, e.g. a container's procfs and the
+    /// host's, only `other` is kept.
+    pub fn merge(self, other: Metrics, capacity: usize) -> Metrics {
+        if self.roots != other.roots {
+            let empty = Metrics {
+                metrics: Vec::new(),
+                smoothed: Vec::new(),
+                roots: other.roots.clone(),
+            };
+            return empty.merge(other, capacity);
+        }
+        let smoothed = if self.smoothed.is_empty() {
+            other.smoothed
+        } else {
+            self.smoothed
+        };
         let mut metrics = self
             .metrics
             .into_iter()
-            .chain(other.metrics.into_iter())
+            .chain(other.metrics)
             .collect::<Vec<TimeTaggedMetric>>();
         metrics.sort_unstable_by_key(|a| a.time);
         metrics.reverse();
-        metrics.truncate(3);
-        Metrics { metrics }
-    }
-
-    pub fn get_rate(&self) -> Option<MetricRate> {
-        let len = self.metrics.len();
-        if len > 1 {
-            let m1 = self.metrics.first()?;
-            let m2 = self.metrics.last()?;
-            let dtime = m1.time - m2.time;
-            assert!(dtime > std::time::Duration::new(1, 0));
-            let rate = MetricRate {
-                network: m1.network.diff(&m2.network, &dtime),
-                cpu: m1.cpu,
-            };
-            Some(rate)
-        } else {
-            None
+        // Samples from before a reboot can not be compared with the current ones
+        if let Some(boot_id) = metrics.first().and_then(|m| m.boot_id.clone()) {
+            metrics.retain(|m| m.boot_id.as_ref().is_none_or(|id| *id == boot_id));
+        }
+        // Within a boot the monotonic clock orders the samples even if the wall clock was set
+        if metrics.iter().all(|m| m.clocks.is_some()) {
+            metrics.sort_by_key(|m| m.clocks.map(|c| std::cmp::Reverse(c.monotonic)));
+        }
+        if let Some(gap) = metrics
+            .windows(2)
+            .position(|pair| pair[0].suspended_since(&pair[1]))
+        {
+            metrics.truncate(gap + 1);
+        }
+        metrics.truncate(capacity);
+        let mut merged = Metrics {
+            metrics,
+            smoothed,
+            roots: self.roots,
+        };
+        merged.update_smoothed();
+        merged
+    }
+
+    /// Folds the samples newer than each moving average into it
+    fn update_smoothed(&mut self) {
+        let newest = match self.metrics.first() {
+            Some(newest) => newest,
+            None => return,
+        };
+        let latest = self.rates().pop();
+        for ewma in self.smoothed.iter_mut() {
+            if ewma.time == newest.time {
+                continue;
+            }
+            let old = self.metrics.iter().find(|m| m.time == ewma.time);
+            match old.filter(|old| newest.elapsed_since(old) > Some(std::time::Duration::ZERO)) {
+                Some(old) => ewma.update(&rate_between(newest, old)),
+                // The average fell out of the history or is not older, start over
+                None => {
+                    if let Some(latest) = &latest {
+                        *ewma = Ewma::new(ewma.half_life, latest);
+                    }
+                }
+            }
         }
     }
+
+    /// Starts keeping a moving average with `half_life` if there is none yet
+    pub fn smooth(&mut self, half_life: std::time::Duration) {
+        let position = self.smoothed.iter().position(|e| e.half_life == half_life);
+        let ewma = match position {
+            Some(position) => self.smoothed.remove(position),
+            None => match self.rates().pop() {
+                Some(latest) => Ewma::new(half_life, &latest),
+                None => return,
+            },
+        };
+        self.smoothed.insert(0, ewma);
+        self.smoothed.truncate(MAX_SMOOTHED);
+    }
+
+    /// Rate over the window, with the network and disk rates replaced by
+    /// their moving average. The one kept for the half-life is used if it is
+    /// up to date, otherwise it is computed over the history without keeping it.
+    pub fn rate(&self, averaging: &Averaging) -> Result<MetricRate, RateError> {
+        let mut rate = self.get_rate(averaging.window())?;
+        if let Some(half_life) = averaging.half_life() {
+            let kept = self
+                .smoothed
+                .iter()
+                .find(|e| e.half_life == half_life && e.time == rate.time);
+            match kept {
+                Some(ewma) => ewma.apply(&mut rate),
+                None => {
+                    if let Some(ewma) = self.average(half_life) {
+                        ewma.apply(&mut rate);
+                    }
+                }
+            }
+        }
+        Ok(rate)
+    }
+
+    /// Moving average over every rate in the history, oldest first
+    fn average(&self, half_life: std::time::Duration) -> Option<Ewma> {
+        let mut rates = self.rates().into_iter();
+        let mut ewma = Ewma::new(half_life, &rates.next()?);
+        rates.for_each(|rate| ewma.update(&rate));
+        Some(ewma)
+    }
+
+    /// Rate of the newest sample against the newest one more than `window`
+    /// older, or the oldest one if there is none. Without a window the rate
+    /// spans the last few samples.
+    pub fn get_rate(&self, window: Option<std::time::Duration>) -> Result<MetricRate, RateError> {
+        let (m1, oldest) = match (self.metrics.first(), self.metrics.last()) {
+            (Some(m1), Some(oldest)) if self.metrics.len() > 1 => (m1, oldest),
+            _ => return Err(RateError::NotEnoughData),
+        };
+        let m2 = match window {
+            Some(window) => self
+                .metrics
+                .iter()
+                .find(|m| m1.elapsed_since(m) > Some(window.max(MIN_RATE_WINDOW)))
+                .unwrap_or(oldest),
+            None => self.metrics.get(RATE_SAMPLES - 1).unwrap_or(oldest),
+        };
+        // Only samples without monotonic clocks depend on the wall clock
+        if (m1.clocks.is_none() || m2.clocks.is_none()) && m1.time > now() + MAX_CLOCK_SKEW {
+            return Err(RateError::ClockSkew);
+        }
+        match m1.elapsed_since(m2) {
+            Some(elapsed) if elapsed > MIN_RATE_WINDOW => Ok(rate_between(m1, m2)),
+            Some(_) => Err(RateError::WindowTooShort),
+            None => Err(RateError::ClockSkew),
+        }
+    }
+
+    /// Rates between every pair of consecutive samples, oldest first
+    pub fn rates(&self) -> Vec<MetricRate> {
+        self.metrics
+            .windows(2)
+            .rev()
+            .filter(|pair| pair[0].elapsed_since(&pair[1]) > Some(std::time::Duration::ZERO))
+            .map(|pair| rate_between(&pair[0], &pair[1]))
+            .collect()
+    }
 }
 
-pub fn get_metrics() -> Result<Metrics, MetricError> {
-    let dur: std::time::Duration = std::time::SystemTime::now()
+#[cfg(target_os = "linux")]
+fn get_boot_id(roots: &Roots) -> Option<String> {
+    let id = std::fs::read_to_string(roots.proc("sys/kernel/random/boot_id")).ok()?;
+    Some(id.trim().to_string())
+}
+
+#[cfg(target_os = "macos")]
+fn get_boot_id(_: &Roots) -> Option<String> {
+    use sysctl::Sysctl;
+    let ctl = sysctl::Ctl::new("kern.bootsessionuuid").ok()?;
+    ctl.value_string().ok()
+}
+
+/// Time since the Unix epoch, zero if the clock is set before it
+fn now() -> std::time::Duration {
+    std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
-        .unwrap();
-    let network_metrics = get_network_metrics()?;
-    let cpu_metrics = get_cpu_metrics()?;
-    let m = TimeTaggedMetric {
-        time: dur,
-        network: network_metrics,
-        cpu: cpu_metrics,
-    };
-    let metrics = Metrics::new(m);
-    Ok(metrics)
+        .unwrap_or_default()
 }
 
-#[derive(Debug)]
-pub struct MetricRate {
-    pub network: network::NetworkMetricRate,
-    pub cpu: cpu::CPUMetrics,
+/// Sources that failed to collect, by name
+pub type Failures = Vec<(&'static str, MetricError)>;
+
+/// Samples every source, sources that fail are left at their default and
+/// marked as failed in the sample
+pub fn get_metrics() -> Result<Metrics, MetricError> {
+    get_metrics_from(&Roots::default(), &Selection::default()).map(|(metrics, _)| metrics)
+}
+
+/// Samples every source reading procfs and sysfs under `roots`, filesystems
+/// only at the mounts of the `selection` if it names any. Sources that fail
+/// are marked as failed in the sample and returned alongside, it only fails
+/// if every source failed.
+pub fn get_metrics_from(
+    roots: &Roots,
+    selection: &Selection,
+) -> Result<(Metrics, Failures), MetricError> {
+    let (sample, mut failures) = collect(now(), roots, selection);
+    if failures.len() == SOURCES {
+        return Err(failures.swap_remove(0).1);
+    }
+    Ok((Metrics::from_roots(sample, roots.clone()), failures))
+}
+
+fn serialize_secs<S: serde::Serializer>(
+    d: &std::time::Duration,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(d.as_secs_f64())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn sample(secs: u64, ibytes: u64) -> Metrics {
+        booted(secs, ibytes, None)
+    }
+
+    fn booted(secs: u64, ibytes: u64, boot_id: Option<&str>) -> Metrics {
+        let mut network = NetworkMetrics::default();
+        network.interfaces.insert(
+            "eth0".to_string(),
+            InterfaceMetrics {
+                ibytes,
+                physical: true,
+                ..InterfaceMetrics::default()
+            },
+        );
+        Metrics::new(TimeTaggedMetric {
+            time: Duration::new(secs, 0),
+            network,
+            boot_id: boot_id.map(str::to_string),
+            ..TimeTaggedMetric::default()
+        })
+    }
+
+    #[test]
+    fn test_history() {
+        let metrics = (1..6).fold(sample(0, 0), |m, i| m.merge(sample(i * 2, i * i * 100), 4));
+        assert_eq!(metrics.metrics.len(), 4);
+        assert_eq!(metrics.latest().unwrap().time, Duration::new(10, 0));
+        let download = |rate: &MetricRate| rate.network.interfaces["eth0"].ibytes;
+        let rates = metrics.rates();
+        assert_eq!(
+            rates.iter().map(download).collect::<Vec<f64>>(),
+            vec![250.0, 350.0, 450.0]
+        );
+        // The rate spans the newest three samples
+        let rate = metrics.get_rate(None).unwrap();
+        assert_eq!(rate.window, Duration::new(4, 0));
+        assert_eq!(download(&rate), 400.0);
+        let instantaneous = metrics.get_rate(Some(Duration::new(0, 0))).unwrap();
+        assert_eq!(instantaneous.window, Duration::new(2, 0));
+        let rate = metrics.get_rate(Some(Duration::new(5, 0))).unwrap();
+        assert_eq!(rate.window, Duration::new(6, 0));
+        let rate = metrics.get_rate(Some(Duration::new(60, 0))).unwrap();
+        assert_eq!(rate.window, Duration::new(6, 0));
+    }
+
+    #[test]
+    fn test_reboot() {
+        let metrics = booted(0, 5000, Some("a"))
+            .merge(booted(2, 6000, Some("a")), 4)
+            .merge(booted(4, 1000, Some("b")), 4);
+        assert_eq!(metrics.metrics.len(), 1);
+        assert_eq!(metrics.get_rate(None).err(), Some(RateError::NotEnoughData));
+        let metrics = metrics.merge(booted(6, 3000, Some("b")), 4);
+        let rate = metrics.get_rate(None).unwrap();
+        assert_eq!(rate.network.interfaces["eth0"].ibytes, 1000.0);
+    }
+
+    #[test]
+    fn test_other_roots() {
+        let host = |secs, ibytes| {
+            let roots = Roots {
+                proc: "/host/proc".into(),
+                ..Roots::default()
+            };
+            Metrics::from_roots(sample(secs, ibytes).metrics.remove(0), roots)
+        };
+        let metrics = sample(0, 5000)
+            .merge(sample(2, 6000), 4)
+            .merge(host(4, 1000), 4);
+        assert_eq!(metrics.metrics.len(), 1);
+        let metrics = metrics.merge(host(6, 3000), 4);
+        let rate = metrics.get_rate(None).unwrap();
+        assert_eq!(rate.network.interfaces["eth0"].ibytes, 1000.0);
+    }
+
+    #[test]
+    fn test_smoothing() {
+        let averaging = Averaging {
+            window: Some(0),
+            half_life: Some(4),
+        };
+        let ibytes =
+            |metrics: &Metrics| metrics.rate(&averaging).unwrap().network.interfaces["eth0"].ibytes;
+        let mut metrics = sample(0, 0)
+            .merge(sample(4, 4000), 4)
+            .merge(sample(8, 16000), 4);
+        // Without a kept average it is computed over the history
+        assert_eq!(ibytes(&metrics), 2000.0);
+        // The kept one is seeded from the newest rate and refined by later samples
+        metrics.smooth(Duration::new(4, 0));
+        assert_eq!(ibytes(&metrics), 3000.0);
+        let metrics = metrics.merge(sample(12, 16000), 4);
+        assert_eq!(ibytes(&metrics), 1500.0);
+    }
+
+    #[test]
+    fn test_rate_errors() {
+        let metrics = sample(10, 0).merge(sample(11, 1000), 3);
+        assert_eq!(
+            metrics.get_rate(None).err(),
+            Some(RateError::WindowTooShort)
+        );
+        let future = now() + Duration::new(60, 0);
+        let metrics = sample(10, 0).merge(sample(future.as_secs(), 1000), 3);
+        assert_eq!(metrics.get_rate(None).err(), Some(RateError::ClockSkew));
+    }
+
+    fn clocked(secs: u64, ibytes: u64, monotonic: u64, boottime: u64) -> Metrics {
+        let mut metrics = sample(secs, ibytes);
+        metrics.metrics[0].clocks = Some(Clocks {
+            monotonic: Duration::new(monotonic, 0),
+            boottime: Duration::new(boottime, 0),
+        });
+        metrics
+    }
+
+    #[test]
+    fn test_clocks() {
+        // The wall clock was set back an hour between the samples
+        let metrics = clocked(3600, 0, 10, 10).merge(clocked(2, 2000, 12, 12), 4);
+        assert_eq!(metrics.latest().unwrap().time, Duration::new(2, 0));
+        let rate = metrics.get_rate(None).unwrap();
+        assert_eq!(rate.window, Duration::new(2, 0));
+        assert_eq!(rate.network.interfaces["eth0"].ibytes, 1000.0);
+        // Woke up after a night of suspend
+        let metrics = metrics.merge(clocked(8 * 3600, 5000, 14, 8 * 3600), 4);
+        assert_eq!(metrics.metrics.len(), 1);
+        assert_eq!(metrics.get_rate(None).err(), Some(RateError::NotEnoughData));
+        let metrics = metrics.merge(clocked(8 * 3600 + 2, 7000, 16, 8 * 3600 + 2), 4);
+        let rate = metrics.get_rate(None).unwrap();
+        assert_eq!(rate.window, Duration::new(2, 0));
+    }
+
+    #[test]
+    fn test_registry() {
+        let mut names = metric_defs().map(|def| def.name).collect::<Vec<&str>>();
+        assert_eq!(names.first(), Some(&"download"));
+        let count = names.len();
+        names.sort_unstable();
+        names.dedup();
+        assert_eq!(names.len(), count, "metric names must be unique");
+
+        let mut labels = metric_defs()
+            .filter_map(|def| def.label)
+            .collect::<Vec<&str>>();
+        let count = labels.len();
+        labels.sort_unstable();
+        labels.dedup();
+        assert_eq!(labels.len(), count, "metric labels must be unique");
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_roots() {
+        let fixtures =
+            std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("src/metrics/test/root");
+        let roots = Roots {
+            proc: fixtures.join("proc"),
+            sys: fixtures.join("sys"),
+            host: fixtures.clone(),
+        };
+        let (metrics, failures) = get_metrics_from(&roots, &Selection::default()).unwrap();
+        assert!(failures.is_empty(), "{:?}", failures);
+        let sample = metrics.latest().unwrap();
+        assert_eq!(
+            sample.boot_id.as_deref(),
+            Some("6f0b4c3e-1d2a-4e5f-9a8b-7c6d5e4f3a21")
+        );
+        assert_eq!(sample.cpu.m1, 0.52);
+        assert_eq!(sample.cpu_stat.cores.len(), 2);
+        assert_eq!(sample.memory.total, 16314244 * 1024);
+        let eth0 = sample.network.interfaces["eth0"];
+        assert!(eth0.physical);
+        assert_eq!(eth0.index, 4);
+        assert!(!sample.network.interfaces["wg0"].physical);
+        assert!(sample.disk.devices["sda"].physical);
+        assert!(!sample.disk.devices["sda1"].physical);
+        // Only the root of the mount table exists below the host root
+        let mounts = sample.filesystems.iter().map(|fs| fs.mount_point.as_str());
+        assert_eq!(mounts.collect::<Vec<_>>(), vec!["/"]);
+
+        let missing = Roots {
+            proc: fixtures.join("missing"),
+            ..roots
+        };
+        assert!(get_metrics_from(&missing, &Selection::default()).is_err());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_partial_roots() {
+        let fixtures =
+            std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("src/metrics/test/root");
+        let proc = std::env::temp_dir().join(format!("diffmetrik-partial-{}", std::process::id()));
+        std::fs::create_dir_all(proc.join("net")).unwrap();
+        for file in &["net/dev", "loadavg"] {
+            std::fs::copy(fixtures.join("proc").join(file), proc.join(file)).unwrap();
+        }
+        let roots = Roots {
+            proc: proc.clone(),
+            sys: fixtures.join("sys"),
+            ..Roots::default()
+        };
+        let (metrics, failures) = get_metrics_from(&roots, &Selection::default()).unwrap();
+        std::fs::remove_dir_all(&proc).unwrap();
+        let failed = failures
+            .iter()
+            .map(|(source, _)| *source)
+            .collect::<Vec<_>>();
+        assert_eq!(failed, vec!["cpu_stat", "memory", "disk", "filesystems"]);
+        let sample = metrics.latest().unwrap();
+        assert_eq!(sample.failed, failed);
+        assert_eq!(sample.network.interfaces["eth0"].ibytes, 5610486);
+        assert_eq!(sample.cpu.m1, 0.52);
+        assert!(sample.disk.devices.is_empty());
+
+        let older = TimeTaggedMetric {
+            time: sample.time - Duration::new(2, 0),
+            ..TimeTaggedMetric::default()
+        };
+        let rate = rate_between(sample, &older);
+        assert_eq!(
+            rate.failed,
+            vec!["cpu_utilization", "memory", "disk", "filesystems"]
+        );
+        let def = |name| metric_defs().find(|def| def.name == name).unwrap();
+        assert!(rate.available(def("download")));
+        assert!(rate.available(def("cpu")));
+        assert!(!rate.available(def("cpu-usage")));
+        assert!(!rate.available(def("memory")));
+    }
 }
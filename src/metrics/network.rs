@@ -1,6 +1,9 @@
-use human_format::{Formatter, Scales};
-#[cfg(target_os = "macos")]
+use super::counter_delta;
+use super::format::Unit;
+use super::source::{single, FamilyDef, MetricDef, MetricSource, Samples, Selection};
+use super::{MetricError, MetricRate, Roots, TimeTaggedMetric};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 #[cfg(target_os = "macos")]
 use std::convert::TryInto;
 #[cfg(target_os = "macos")]
@@ -9,12 +12,14 @@ use std::time::Duration;
 #[cfg(target_os = "macos")]
 use sysctl::Sysctl;
 
+/// Reading the interface counters failed
 #[derive(Debug)]
 pub enum NetworkError {
-    #[allow(dead_code)]
+    /// A sysctl value could not be read
     CtlError,
-    #[allow(dead_code)]
+    /// The interface counters could not be read
     GetMetrics(String),
+    /// Reading the counters file failed
     IO(String),
 }
 
@@ -113,54 +118,257 @@ fn parse_msghdr(data: &[u8], offset: usize) -> (Option<if_msghdr2>, Option<usize
     }
 }
 
-fn make_formatter() -> Formatter {
-    let suffixes = vec![" ", "k", "M", "G", "T"];
-    let mut scales = Scales::new();
-    scales.with_base(1024).with_suffixes(suffixes);
-    let mut f = Formatter::new();
-    f.with_scales(scales);
-    f.with_units("B");
-    f.with_decimals(2);
-    f
+/// Declares the counters of an interface along with their per second rates
+macro_rules! interface_counters {
+    ($($(#[$doc:meta])* $field:ident,)*) => {
+        /// Cumulative counters of an interface since it was created
+        #[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+        pub struct InterfaceMetrics {
+            $(
+                $(#[$doc])*
+                #[serde(default)]
+                pub $field: u64,
+            )*
+            /// Backed by a hardware device, as opposed to loopback, bridges or tunnels
+            pub physical: bool,
+            /// Interface index, changes when an interface of the same name is recreated.
+            /// 0 if unknown
+            #[serde(default)]
+            pub index: u32,
+        }
+
+        /// Counter increments per second
+        #[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+        pub struct InterfaceRate {
+            $($(#[$doc])* #[serde(default)] pub $field: f64,)*
+            /// See `InterfaceMetrics::physical`
+            pub physical: bool,
+        }
+
+        impl InterfaceRate {
+            /// Moves every rate towards `sample` by `alpha`, between 0 and 1
+            fn smooth(&self, sample: &InterfaceRate, alpha: f64) -> InterfaceRate {
+                InterfaceRate {
+                    $($field: self.$field + alpha * (sample.$field - self.$field),)*
+                    physical: sample.physical,
+                }
+            }
+        }
+
+        impl InterfaceMetrics {
+            /// `None` if the interface was recreated or any of its counters reset
+            fn rate(&self, old: &InterfaceMetrics, secs: f64) -> Option<InterfaceRate> {
+                if self.index != old.index && self.index != 0 && old.index != 0 {
+                    return None;
+                }
+                Some(InterfaceRate {
+                    $($field: counter_delta(self.$field, old.$field)? as f64 / secs,)*
+                    physical: self.physical,
+                })
+            }
+        }
+
+        impl std::ops::Add for InterfaceRate {
+            type Output = InterfaceRate;
+
+            fn add(self, other: InterfaceRate) -> InterfaceRate {
+                InterfaceRate {
+                    $($field: self.$field + other.$field,)*
+                    physical: self.physical || other.physical,
+                }
+            }
+        }
+    };
 }
 
-fn format_bytes(formatter: &Formatter, bytes: f64) -> String {
-    format!("{:>10}/s", formatter.format(bytes))
+interface_counters! {
+    /// Bytes received
+    ibytes,
+    /// Packets received
+    ipackets,
+    /// Receive errors
+    ierrors,
+    /// Dropped or missed on receive
+    idrops,
+    /// Receive FIFO overruns
+    ififo,
+    /// Length, overrun, CRC and frame alignment errors
+    iframe,
+    /// Compressed packets received
+    icompressed,
+    /// Multicast packets received
+    multicast,
+    /// Bytes sent
+    obytes,
+    /// Packets sent
+    opackets,
+    /// Send errors
+    oerrors,
+    /// Dropped on send
+    odrops,
+    /// Send FIFO underruns
+    ofifo,
+    /// Collisions on shared media
+    collisions,
+    /// Carrier, aborted, window and heartbeat errors
+    ocarrier,
+    /// Compressed packets sent
+    ocompressed,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Counters of every network interface
+#[derive(Serialize, Deserialize, Debug, Default)]
 pub struct NetworkMetrics {
-    pub total_ibytes: u64,
-    pub total_obytes: u64,
+    /// Keyed by interface name
+    pub interfaces: BTreeMap<String, InterfaceMetrics>,
 }
 
 impl NetworkMetrics {
+    /// Rates of every interface since the `old` sample, `dtime` earlier.
+    /// Interfaces that are new, were recreated or reset their counters are left out
     pub fn diff(&self, old: &NetworkMetrics, dtime: &Duration) -> NetworkMetricRate {
-        let formatter = make_formatter();
-        let ibyte_rate = (self.total_ibytes as f64 - old.total_ibytes as f64) / dtime.as_secs_f64();
-        let obyte_rate = (self.total_obytes as f64 - old.total_obytes as f64) / dtime.as_secs_f64();
-        NetworkMetricRate {
-            ibyte_rate: format_bytes(&formatter, ibyte_rate),
-            obyte_rate: format_bytes(&formatter, obyte_rate),
-        }
+        let secs = dtime.as_secs_f64();
+        let interfaces = self
+            .interfaces
+            .iter()
+            .filter_map(|(name, new)| {
+                let old = old.interfaces.get(name)?;
+                Some((name.clone(), new.rate(old, secs)?))
+            })
+            .collect();
+        NetworkMetricRate { interfaces }
     }
 }
 
+/// Network metric summing `$value` over the selected interfaces
+macro_rules! network_metric {
+    ($name:expr, $label:expr, $unit:expr, |$rate:ident| $value:expr) => {
+        MetricDef::single($name, $label, $unit, |rate, selection, field| {
+            let $rate = total(rate, selection, field);
+            single($value)
+        })
+        .with_any_field()
+    };
+}
+
+/// Counter of every interface in the sample
+macro_rules! interface_counter {
+    ($name:expr, $help:expr, $field:ident) => {
+        FamilyDef::counter($name, $help, |m| per_interface(m, |i| i.$field as f64))
+    };
+}
+
+impl MetricSource for NetworkMetrics {
+    type Rate = NetworkMetricRate;
+
+    const METRICS: &'static [MetricDef] = &[
+        network_metric!("download", "D", Unit::BytesPerSecond, |n| n.ibytes),
+        network_metric!("upload", "U", Unit::BytesPerSecond, |n| n.obytes),
+        network_metric!("rx-packets", "RXP", Unit::PerSecond, |n| n.ipackets),
+        network_metric!("tx-packets", "TXP", Unit::PerSecond, |n| n.opackets),
+        network_metric!("rx-errors", "RXE", Unit::PerSecond, |n| n.ierrors),
+        network_metric!("tx-errors", "TXE", Unit::PerSecond, |n| n.oerrors),
+        network_metric!("rx-drops", "RXD", Unit::PerSecond, |n| n.idrops),
+        network_metric!("tx-drops", "TXD", Unit::PerSecond, |n| n.odrops),
+        network_metric!("errors", "E", Unit::PerSecond, |n| n.ierrors + n.oerrors),
+        network_metric!("drops", "DR", Unit::PerSecond, |n| n.idrops + n.odrops),
+        network_metric!("collisions", "COL", Unit::PerSecond, |n| n.collisions),
+    ];
+
+    const FAMILIES: &'static [FamilyDef] = &[
+        interface_counter!("network_receive_bytes_total", "Bytes received", ibytes),
+        interface_counter!(
+            "network_receive_packets_total",
+            "Packets received",
+            ipackets
+        ),
+        interface_counter!("network_receive_errs_total", "Receive errors", ierrors),
+        interface_counter!(
+            "network_receive_drop_total",
+            "Packets dropped on receive",
+            idrops
+        ),
+        interface_counter!("network_transmit_bytes_total", "Bytes transmitted", obytes),
+        interface_counter!(
+            "network_transmit_packets_total",
+            "Packets transmitted",
+            opackets
+        ),
+        interface_counter!("network_transmit_errs_total", "Transmit errors", oerrors),
+        interface_counter!(
+            "network_transmit_drop_total",
+            "Packets dropped on transmit",
+            odrops
+        ),
+        interface_counter!("network_collisions_total", "Collisions", collisions),
+        FamilyDef::gauge(
+            "network_physical",
+            "1 if the interface is backed by a hardware device",
+            |m| per_interface(m, |i| if i.physical { 1.0 } else { 0.0 }),
+        ),
+    ];
+
+    fn collect(roots: &Roots, _: &Selection) -> Result<NetworkMetrics, MetricError> {
+        Ok(get_network_metrics(roots)?)
+    }
+
+    fn diff(&self, old: &NetworkMetrics, dtime: &Duration) -> NetworkMetricRate {
+        NetworkMetrics::diff(self, old, dtime)
+    }
+}
+
+/// Values of every interface in the sample, labelled by its name
+fn per_interface(m: &TimeTaggedMetric, value: fn(&InterfaceMetrics) -> f64) -> Samples {
+    m.network
+        .interfaces
+        .iter()
+        .map(|(name, i)| (vec![("interface", name.clone())], value(i)))
+        .collect()
+}
+
+/// Sum over the interface named by the field, or over the selected ones
+fn total(rate: &MetricRate, selection: &Selection, field: Option<&str>) -> InterfaceRate {
+    let interfaces = match field {
+        Some(field) => vec![field],
+        None => selection.interfaces.iter().map(String::as_str).collect(),
+    };
+    rate.network.total(&InterfaceFilter::new(interfaces))
+}
+
 #[cfg(target_os = "macos")]
-pub fn get_network_metrics() -> Result<NetworkMetrics, NetworkError> {
+pub fn get_network_metrics(_: &Roots) -> Result<NetworkMetrics, NetworkError> {
     let oid: Vec<i32> = vec![libc::CTL_NET, libc::PF_ROUTE, 0, 0, libc::NET_RT_IFLIST2, 0];
     let ctl = sysctl::Ctl::Oid(oid);
     let vval = ctl.value().map_err(|_| NetworkError::CtlError)?;
     if let sysctl::CtlValue::Node(nvec) = vval {
         let mut next = Some(0);
-        let mut total_ibytes: u64 = 0;
-        let mut total_obytes: u64 = 0;
+        let mut interfaces = BTreeMap::new();
         loop {
             let (h1, n) = parse_msghdr(&nvec, next.unwrap());
             if let Some(h1) = h1 {
-                if h1.ifm_data.ifi_type == 6 {
-                    total_ibytes += h1.ifm_data.ifi_ibytes;
-                    total_obytes += h1.ifm_data.ifi_obytes;
+                let mut name = [0 as libc::c_char; libc::IF_NAMESIZE];
+                let ptr = unsafe { libc::if_indextoname(h1.ifm_index.into(), name.as_mut_ptr()) };
+                if !ptr.is_null() {
+                    let name = unsafe { std::ffi::CStr::from_ptr(name.as_ptr()) };
+                    interfaces.insert(
+                        name.to_string_lossy().into_owned(),
+                        InterfaceMetrics {
+                            ibytes: h1.ifm_data.ifi_ibytes,
+                            ipackets: h1.ifm_data.ifi_ipackets,
+                            ierrors: h1.ifm_data.ifi_ierrors,
+                            idrops: h1.ifm_data.ifi_iqdrops,
+                            multicast: h1.ifm_data.ifi_imcasts,
+                            obytes: h1.ifm_data.ifi_obytes,
+                            opackets: h1.ifm_data.ifi_opackets,
+                            oerrors: h1.ifm_data.ifi_oerrors,
+                            odrops: h1.ifm_snd_drops as u64,
+                            collisions: h1.ifm_data.ifi_collisions,
+                            // IFT_ETHER, covers both wired and wireless interfaces
+                            physical: h1.ifm_data.ifi_type == 6,
+                            index: h1.ifm_index.into(),
+                            ..InterfaceMetrics::default()
+                        },
+                    );
                 }
             }
             next = n;
@@ -168,13 +376,7 @@ pub fn get_network_metrics() -> Result<NetworkMetrics, NetworkError> {
                 break;
             }
         }
-        // dbg!(&total_ibytes);
-        // dbg!(&total_obytes);
-        let metrics = NetworkMetrics {
-            total_ibytes,
-            total_obytes,
-        };
-        Ok(metrics)
+        Ok(NetworkMetrics { interfaces })
     } else {
         Err(NetworkError::GetMetrics(
             "value retrieved from ctl was not a node".to_string(),
@@ -183,50 +385,157 @@ pub fn get_network_metrics() -> Result<NetworkMetrics, NetworkError> {
 }
 
 #[cfg(target_os = "linux")]
-pub fn get_network_metrics() -> Result<NetworkMetrics, NetworkError> {
-    let path = "/proc/net/dev";
-    let proc = std::fs::read_to_string(path)?;
-    parse_linux_proc_net_dev(&proc)
+pub fn get_network_metrics(roots: &Roots) -> Result<NetworkMetrics, NetworkError> {
+    let proc = std::fs::read_to_string(roots.proc("net/dev"))?;
+    let sys = |iface: &str| roots.sys("class/net").join(iface);
+    parse_linux_proc_net_dev(
+        &proc,
+        |iface| sys(iface).join("device").exists(),
+        |iface| {
+            std::fs::read_to_string(sys(iface).join("ifindex"))
+                .ok()
+                .and_then(|index| index.trim().parse().ok())
+                .unwrap_or(0)
+        },
+    )
 }
 
-#[derive(Debug)]
+/// Rates of every network interface
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct NetworkMetricRate {
-    pub ibyte_rate: String,
-    pub obyte_rate: String,
+    /// Keyed by interface name
+    pub interfaces: BTreeMap<String, InterfaceRate>,
+}
+
+impl NetworkMetricRate {
+    /// Exponentially weighted average of `self` and `sample`, interfaces
+    /// missing from `sample` are dropped and new ones start at their sample
+    pub fn smooth(&self, sample: &NetworkMetricRate, alpha: f64) -> NetworkMetricRate {
+        let interfaces = sample
+            .interfaces
+            .iter()
+            .map(|(name, new)| {
+                let smoothed = match self.interfaces.get(name) {
+                    Some(old) => old.smooth(new, alpha),
+                    None => *new,
+                };
+                (name.clone(), smoothed)
+            })
+            .collect();
+        NetworkMetricRate { interfaces }
+    }
+
+    /// Sum over the interfaces accepted by `filter`
+    pub fn total(&self, filter: &InterfaceFilter) -> InterfaceRate {
+        self.interfaces
+            .iter()
+            .filter(|(name, rate)| filter.accepts(name, rate.physical))
+            .fold(InterfaceRate::default(), |acc, (_, rate)| acc + *rate)
+    }
+}
+
+/// Selects interfaces by glob patterns, patterns starting with `!` exclude.
+///
+/// Without any include pattern all physical interfaces are selected.
+#[derive(Debug, Default)]
+pub struct InterfaceFilter<'a> {
+    include: Vec<&'a str>,
+    exclude: Vec<&'a str>,
+}
+
+impl<'a> InterfaceFilter<'a> {
+    /// Filter of the include and `!` prefixed exclude patterns
+    pub fn new<I: IntoIterator<Item = &'a str>>(patterns: I) -> InterfaceFilter<'a> {
+        let mut filter = InterfaceFilter::default();
+        for pattern in patterns {
+            match pattern.strip_prefix('!') {
+                Some(exclude) => filter.exclude.push(exclude),
+                None => filter.include.push(pattern),
+            }
+        }
+        filter
+    }
+
+    /// Whether `iface` is selected
+    pub fn accepts(&self, iface: &str, physical: bool) -> bool {
+        let included = if self.include.is_empty() {
+            physical
+        } else {
+            self.include.iter().any(|p| glob_match(p, iface))
+        };
+        included && !self.exclude.iter().any(|p| glob_match(p, iface))
+    }
+}
+
+/// Matches `*` against any sequence and `?` against any single character
+fn glob_match(pattern: &str, s: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<char>>();
+    let s = s.chars().collect::<Vec<char>>();
+    let (mut p, mut i) = (0, 0);
+    // Position of the last `*` and the input position it was tried at
+    let mut backtrack: Option<(usize, usize)> = None;
+    while i < s.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, i));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == s[i] => {
+                p += 1;
+                i += 1;
+            }
+            _ => match backtrack {
+                Some((star, at)) => {
+                    p = star + 1;
+                    i = at + 1;
+                    backtrack = Some((star, at + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
 }
 
 #[derive(Debug)]
 struct LinuxProcNetDevLine {
-    // iface: String,
-    // rx_bytes: u64,
-    // rx_packets: u64,
-    // rx_errors: u64,
-    // rx_dropped_missed: u64,
-    // rx_fifo_errors: u64,
-    // rx_length_over_ctc_frame_errors: u64,
-    // rx_compressed: u64,
-    // multicast: u64,
-    // tx_bytes: u64,
-    // tx_packets: u64,
-    // tx_errors: u64,
-    // tx_dropped: u64,
-    // tx_fifo_errors: u64,
-    // collisions: u64,
-    // tx_carrier_aborted_window_heartbeat_errors: u64,
-    // tx_compressed: u64,
+    iface: String,
+    rx_bytes: u64,
+    rx_packets: u64,
+    rx_errors: u64,
+    rx_dropped_missed: u64,
+    rx_fifo_errors: u64,
+    rx_length_over_ctc_frame_errors: u64,
+    rx_compressed: u64,
+    multicast: u64,
+    tx_bytes: u64,
+    tx_packets: u64,
+    tx_errors: u64,
+    tx_dropped: u64,
+    tx_fifo_errors: u64,
+    collisions: u64,
+    tx_carrier_aborted_window_heartbeat_errors: u64,
+    tx_compressed: u64,
 }
 
 impl LinuxProcNetDevLine {
     #[cfg(target_os = "linux")]
     fn new(line: &str) -> Result<LinuxProcNetDevLine, NetworkError> {
-        let line = line.split_whitespace().collect::<Vec<&str>>();
-        let iface = line[0].to_string();
-        let line: Vec<u64> = line
-            .iter()
-            .skip(1)
-            .map(|el| el.parse::<u64>())
-            .flatten()
+        // Large counters are not separated from the name by whitespace, e.g. `eth0:12345`
+        let (iface, counters) = line
+            .split_once(':')
+            .ok_or_else(|| NetworkError::GetMetrics(format!("malformed line: {}", line)))?;
+        let iface = iface.trim().to_string();
+        let line: Vec<u64> = counters
+            .split_whitespace()
+            .flat_map(|el| el.parse::<u64>())
             .collect::<Vec<u64>>();
+        if line.len() < 16 {
+            return Err(NetworkError::GetMetrics(format!(
+                "expected 16 counters for {}",
+                iface
+            )));
+        }
         Ok(LinuxProcNetDevLine {
             iface,
             rx_bytes: line[0],
@@ -250,21 +559,44 @@ impl LinuxProcNetDevLine {
 }
 
 #[cfg(target_os = "linux")]
-fn parse_linux_proc_net_dev(s: &str) -> Result<NetworkMetrics, NetworkError> {
-    let lines = s.lines().skip(1).collect::<Vec<&str>>();
-    let lines = lines
-        .iter()
-        .map({ |line| line.trim() })
-        .filter({ |line| line.starts_with("eth") })
-        .map({ |line| LinuxProcNetDevLine::new(line) })
-        .flatten()
-        .collect::<Vec<LinuxProcNetDevLine>>();
-    let total_ibytes = lines.iter().map(|line| line.rx_bytes).sum();
-    let total_obytes = lines.iter().map(|line| line.tx_bytes).sum();
-    Ok(NetworkMetrics {
-        total_ibytes,
-        total_obytes,
-    })
+fn parse_linux_proc_net_dev<F, I>(
+    s: &str,
+    is_physical: F,
+    index: I,
+) -> Result<NetworkMetrics, NetworkError>
+where
+    F: Fn(&str) -> bool,
+    I: Fn(&str) -> u32,
+{
+    let interfaces = s
+        .lines()
+        .skip(2)
+        .flat_map(LinuxProcNetDevLine::new)
+        .map(|line| {
+            let metrics = InterfaceMetrics {
+                ibytes: line.rx_bytes,
+                ipackets: line.rx_packets,
+                ierrors: line.rx_errors,
+                idrops: line.rx_dropped_missed,
+                ififo: line.rx_fifo_errors,
+                iframe: line.rx_length_over_ctc_frame_errors,
+                icompressed: line.rx_compressed,
+                multicast: line.multicast,
+                obytes: line.tx_bytes,
+                opackets: line.tx_packets,
+                oerrors: line.tx_errors,
+                odrops: line.tx_dropped,
+                ofifo: line.tx_fifo_errors,
+                collisions: line.collisions,
+                ocarrier: line.tx_carrier_aborted_window_heartbeat_errors,
+                ocompressed: line.tx_compressed,
+                physical: is_physical(&line.iface),
+                index: index(&line.iface),
+            };
+            (line.iface, metrics)
+        })
+        .collect();
+    Ok(NetworkMetrics { interfaces })
 }
 
 #[cfg(test)]
@@ -272,27 +604,106 @@ mod tests {
     use super::*;
 
     #[cfg(target_os = "linux")]
-    static LINUX_PROC_NET_DEV: &str = include_str!("test/linux_proc_net_dev.txt");
+    static LINUX_PROC_NET_DEV: &str = include_str!("test/root/proc/net/dev");
 
     #[test]
     #[cfg(target_os = "linux")]
     fn test_linux() {
-        let m = super::parse_linux_proc_net_dev(LINUX_PROC_NET_DEV)
-            .ok()
-            .unwrap();
-        assert_eq!(m.total_ibytes, 5610486);
-        assert_eq!(m.total_obytes, 81092);
+        let physical = ["eth0", "enp3s0", "wlp2s0"];
+        let m = super::parse_linux_proc_net_dev(
+            LINUX_PROC_NET_DEV,
+            |iface| physical.contains(&iface),
+            |_| 3,
+        )
+        .ok()
+        .unwrap();
+        assert_eq!(m.interfaces.len(), 8);
+        let eth0 = m.interfaces["eth0"];
+        assert_eq!(eth0.ibytes, 5610486);
+        assert_eq!(eth0.obytes, 81092);
+        assert_eq!(eth0.ipackets, 3990);
+        assert_eq!(eth0.opackets, 1478);
+        assert!(eth0.physical);
+        assert_eq!(eth0.index, 3);
+        let enp3s0 = m.interfaces["enp3s0"];
+        assert_eq!(enp3s0.idrops, 4);
+        assert_eq!(enp3s0.multicast, 1203);
+        assert_eq!(enp3s0.oerrors, 2);
+        assert_eq!(m.interfaces["wlp2s0"].ibytes, 12884901888);
+        assert!(!m.interfaces["wg0"].physical);
+    }
+
+    #[test]
+    fn test_diff() {
+        let iface = |ibytes, obytes, physical| InterfaceMetrics {
+            ibytes,
+            obytes,
+            ierrors: ibytes / 1000,
+            physical,
+            ..InterfaceMetrics::default()
+        };
+        let mut old = NetworkMetrics::default();
+        old.interfaces.insert("eth0".to_string(), iface(0, 0, true));
+        old.interfaces
+            .insert("wlan0".to_string(), iface(0, 0, true));
+        old.interfaces.insert("wg0".to_string(), iface(0, 0, false));
+        let mut new = NetworkMetrics::default();
+        new.interfaces
+            .insert("eth0".to_string(), iface(2000, 200, true));
+        new.interfaces
+            .insert("wlan0".to_string(), iface(4000, 400, true));
+        new.interfaces
+            .insert("wg0".to_string(), iface(8000, 800, false));
+        let rate = new.diff(&old, &Duration::new(2, 0));
+        let total = |patterns: &[&'static str]| {
+            rate.total(&InterfaceFilter::new(patterns.iter().copied()))
+                .ibytes
+        };
+        assert_eq!(total(&[]), 3000.0);
+        assert_eq!(total(&["*"]), 7000.0);
+        assert_eq!(total(&["wg*"]), 4000.0);
+        assert_eq!(total(&["!wlan*"]), 1000.0);
+        assert_eq!(total(&["*", "!eth?"]), 6000.0);
+        assert_eq!(rate.total(&InterfaceFilter::default()).ierrors, 3.0);
+    }
+
+    #[test]
+    fn test_reset() {
+        let iface = |ibytes, index| InterfaceMetrics {
+            ibytes,
+            index,
+            ..InterfaceMetrics::default()
+        };
+        let mut old = NetworkMetrics::default();
+        old.interfaces.insert("eth0".to_string(), iface(4000, 2));
+        old.interfaces.insert("wg0".to_string(), iface(4000, 5));
+        old.interfaces
+            .insert("ppp0".to_string(), iface(3_000_000_000, 7));
+        old.interfaces.insert("usb0".to_string(), iface(4000, 8));
+        let mut new = NetworkMetrics::default();
+        // Counter went backwards without a wrap, e.g. the driver was reloaded
+        new.interfaces.insert("eth0".to_string(), iface(1000, 2));
+        // Recreated, the counters happen to be larger
+        new.interfaces.insert("wg0".to_string(), iface(9000, 6));
+        // A 64-bit counter between 2 and 4 GiB reset, not a 32-bit wrap
+        new.interfaces.insert("ppp0".to_string(), iface(1000, 7));
+        new.interfaces.insert("usb0".to_string(), iface(6000, 8));
+        let rate = new.diff(&old, &Duration::new(2, 0));
+        assert_eq!(
+            rate.interfaces.keys().collect::<Vec<&String>>(),
+            vec!["usb0"]
+        );
+        assert_eq!(rate.interfaces["usb0"].ibytes, 1000.0);
     }
 
     #[test]
-    fn test_formatter() {
-        let f = make_formatter();
-        assert_eq!(format_bytes(&f, 1.0), "   1.00  B/s");
-        assert_eq!(format_bytes(&f, 10.0), "  10.00  B/s");
-        assert_eq!(format_bytes(&f, 100.0), " 100.00  B/s");
-        assert_eq!(format_bytes(&f, 1000.0), "1000.00  B/s");
-        assert_eq!(format_bytes(&f, 10_000.0), "   9.77 kB/s");
-        assert_eq!(format_bytes(&f, 1024.0 * 1024.0), "   1.00 MB/s");
-        assert_eq!(format_bytes(&f, 1024.0 * 1024.0 * 1024.0), "   1.00 GB/s");
+    fn test_glob_match() {
+        assert!(glob_match("eth*", "eth0"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("w*0", "wlp2s0"));
+        assert!(glob_match("en?3s0", "enp3s0"));
+        assert!(glob_match("*s*0", "wlp2s0"));
+        assert!(!glob_match("eth*", "enp3s0"));
+        assert!(!glob_match("wg", "wg0"));
     }
 }
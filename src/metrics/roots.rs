@@ -0,0 +1,58 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Where the Linux sources read procfs and sysfs from, and where the
+/// filesystems they list are measured. Inside a container the host's are
+/// typically mounted at `/host/proc`, `/host/sys` and `/host`.
+/// macOS sources do not read any of them and ignore this.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Roots {
+    /// Mount point of procfs, `/proc` by default
+    pub proc: PathBuf,
+    /// Mount point of sysfs, `/sys` by default
+    pub sys: PathBuf,
+    /// Where the root filesystem of the machine procfs belongs to is mounted,
+    /// `/` by default
+    pub host: PathBuf,
+}
+
+impl Default for Roots {
+    fn default() -> Roots {
+        Roots {
+            proc: PathBuf::from("/proc"),
+            sys: PathBuf::from("/sys"),
+            host: PathBuf::from("/"),
+        }
+    }
+}
+
+impl std::fmt::Display for Roots {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "proc={} sys={} host={}",
+            self.proc.display(),
+            self.sys.display(),
+            self.host.display()
+        )
+    }
+}
+
+impl Roots {
+    /// Path of a file under procfs, e.g. `net/dev`
+    pub fn proc(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.proc.join(path)
+    }
+
+    /// Path of a file under sysfs, e.g. `class/net`
+    pub fn sys(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.sys.join(path)
+    }
+
+    /// Path of a mount point listed in procfs, e.g. `/home`
+    pub fn host(&self, path: impl AsRef<Path>) -> PathBuf {
+        let path = path.as_ref();
+        self.host.join(path.strip_prefix("/").unwrap_or(path))
+    }
+}
@@ -0,0 +1,97 @@
+use super::disk::DiskMetricRate;
+use super::network::NetworkMetricRate;
+use super::MetricRate;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Exponentially weighted moving average of the network and disk rates
+///
+/// Every new rate moves the average by a weight that depends on how much
+/// time it covers, so that a sample from `half_life` ago counts half as much.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Ewma {
+    pub half_life: Duration,
+    /// Time of the newest sample folded into the average
+    pub time: Duration,
+    pub network: NetworkMetricRate,
+    pub disk: DiskMetricRate,
+}
+
+impl Ewma {
+    /// Starts the average at `rate`
+    pub fn new(half_life: Duration, rate: &MetricRate) -> Ewma {
+        Ewma {
+            half_life,
+            time: rate.time,
+            network: rate.network.clone(),
+            disk: rate.disk.clone(),
+        }
+    }
+
+    /// Folds in `rate`, which has to start at the newest sample folded in so
+    /// far. Sources that failed to collect keep their average.
+    pub fn update(&mut self, rate: &MetricRate) {
+        let alpha = if self.half_life.as_secs_f64() > 0.0 {
+            1.0 - 0.5f64.powf(rate.window.as_secs_f64() / self.half_life.as_secs_f64())
+        } else {
+            1.0
+        };
+        self.time = rate.time;
+        if rate.collected("network") {
+            self.network = self.network.smooth(&rate.network, alpha);
+        }
+        if rate.collected("disk") {
+            self.disk = self.disk.smooth(&rate.disk, alpha);
+        }
+    }
+
+    /// Replaces the network and disk rates of `rate` by their averages, unless
+    /// they failed to collect
+    pub fn apply(&self, rate: &mut MetricRate) {
+        if rate.collected("network") {
+            rate.network = self.network.clone();
+        }
+        if rate.collected("disk") {
+            rate.disk = self.disk.clone();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::network::InterfaceRate;
+
+    fn rate(secs: u64, window: u64, ibytes: f64) -> MetricRate {
+        let mut network = NetworkMetricRate::default();
+        network.interfaces.insert(
+            "eth0".to_string(),
+            InterfaceRate {
+                ibytes,
+                ..InterfaceRate::default()
+            },
+        );
+        MetricRate {
+            time: Duration::new(secs, 0),
+            window: Duration::new(window, 0),
+            network,
+            ..MetricRate::default()
+        }
+    }
+
+    #[test]
+    fn test_update() {
+        let mut ewma = Ewma::new(Duration::new(4, 0), &rate(2, 2, 1000.0));
+        // A sample covering one half-life moves the average halfway
+        ewma.update(&rate(6, 4, 3000.0));
+        assert_eq!(ewma.time, Duration::new(6, 0));
+        assert_eq!(ewma.network.interfaces["eth0"].ibytes, 2000.0);
+        // Two samples of half the length have the same effect as one
+        ewma.update(&rate(8, 2, 0.0));
+        ewma.update(&rate(10, 2, 0.0));
+        assert!((ewma.network.interfaces["eth0"].ibytes - 1000.0).abs() < 1e-9);
+        let mut r = rate(10, 2, 0.0);
+        ewma.apply(&mut r);
+        assert!((r.network.interfaces["eth0"].ibytes - 1000.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,169 @@
+use super::format::Unit;
+use super::{MetricError, MetricRate, Roots, TimeTaggedMetric};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// A collector of one kind of sample, e.g. network counters or memory usage.
+///
+/// Sources are listed in the `sources!` registry in `metrics`, which stores
+/// their samples in the history, their rates in `MetricRate`, offers their
+/// metrics on the command line and serves their families in the exporter.
+pub trait MetricSource: Serialize + for<'de> Deserialize<'de> + Default + std::fmt::Debug {
+    /// Computed from two samples, or just the newer one for gauges
+    type Rate: Serialize + std::fmt::Debug;
+
+    /// Metrics formatted from the rate, in the order they are listed
+    const METRICS: &'static [MetricDef];
+
+    /// Families served by the exporter, in the order they are listed
+    const FAMILIES: &'static [FamilyDef];
+
+    /// Takes a sample, Linux sources read procfs and sysfs under `roots`.
+    /// Sources that can measure devices on their own only measure the ones
+    /// the `selection` names, if it names any.
+    fn collect(roots: &Roots, selection: &Selection) -> Result<Self, MetricError>;
+
+    /// Rate between the `old` sample and this one, taken `dtime` later
+    fn diff(&self, old: &Self, dtime: &Duration) -> Self::Rate;
+}
+
+/// Values of a metric, labelled if there can be more than one
+pub type Values = Vec<(Option<String>, f64)>;
+
+/// Validates a field, the error says which fields are valid
+pub type FieldCheck = fn(&str) -> Result<(), String>;
+
+/// A metric offered on the command line
+#[derive(Debug)]
+pub struct MetricDef {
+    /// Lowercase and dashed, e.g. `rx-packets`
+    pub name: &'static str,
+    /// Prefix printed in front of the value when rendered on its own, `None`
+    /// if every value is labelled
+    pub label: Option<&'static str>,
+    /// Decides how values are formatted and which threshold suffixes apply
+    pub unit: Unit,
+    /// Reports a value per core, device or mount point rather than a single one
+    pub multiple: bool,
+    /// Validates the part after the dot in a `{metric.field}` placeholder,
+    /// `None` if the metric does not take a field
+    pub check_field: Option<FieldCheck>,
+    /// Current values, `field` overrides the selection
+    pub values: fn(&MetricRate, &Selection, Option<&str>) -> Values,
+}
+
+impl MetricDef {
+    /// A metric with a single unlabelled value
+    pub const fn single(
+        name: &'static str,
+        label: &'static str,
+        unit: Unit,
+        values: fn(&MetricRate, &Selection, Option<&str>) -> Values,
+    ) -> MetricDef {
+        MetricDef {
+            name,
+            label: Some(label),
+            unit,
+            multiple: false,
+            check_field: None,
+            values,
+        }
+    }
+
+    /// Same metric taking any field
+    pub const fn with_any_field(self) -> MetricDef {
+        MetricDef {
+            check_field: Some(any_field),
+            ..self
+        }
+    }
+}
+
+impl PartialEq for MetricDef {
+    fn eq(&self, other: &MetricDef) -> bool {
+        self.name == other.name
+    }
+}
+
+/// Fields naming an interface, device or mount point are not known in advance
+fn any_field(_: &str) -> Result<(), String> {
+    Ok(())
+}
+
+/// Values of a metric with a single unlabelled value
+pub fn single(value: f64) -> Values {
+    vec![(None, value)]
+}
+
+/// Whether a family served by the exporter only goes up
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FamilyKind {
+    /// A total since boot, e.g. bytes received
+    Counter,
+    /// A current value, e.g. free memory
+    Gauge,
+}
+
+/// Values of a family in one sample, each with its labels
+pub type Samples = Vec<(Vec<(&'static str, String)>, f64)>;
+
+/// A metric family served by the exporter, counters are exported as totals
+/// so that rates are computed over the scrape interval
+#[derive(Debug)]
+pub struct FamilyDef {
+    /// Without the `diffmetrik_` prefix, e.g. `network_receive_bytes_total`
+    pub name: &'static str,
+    /// Counter or gauge
+    pub kind: FamilyKind,
+    /// Description printed in the `HELP` line
+    pub help: &'static str,
+    /// Values in the sample
+    pub samples: fn(&TimeTaggedMetric) -> Samples,
+}
+
+impl FamilyDef {
+    /// A family of totals since boot
+    pub const fn counter(
+        name: &'static str,
+        help: &'static str,
+        samples: fn(&TimeTaggedMetric) -> Samples,
+    ) -> FamilyDef {
+        FamilyDef {
+            name,
+            kind: FamilyKind::Counter,
+            help,
+            samples,
+        }
+    }
+
+    /// A family of current values
+    pub const fn gauge(
+        name: &'static str,
+        help: &'static str,
+        samples: fn(&TimeTaggedMetric) -> Samples,
+    ) -> FamilyDef {
+        FamilyDef {
+            kind: FamilyKind::Gauge,
+            ..FamilyDef::counter(name, help, samples)
+        }
+    }
+}
+
+/// Samples of a family with a single unlabelled value
+pub fn unlabelled(value: f64) -> Samples {
+    vec![(Vec::new(), value)]
+}
+
+/// Narrows down the devices a metric is reported for
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Selection {
+    /// Single block device for the disk throughput metrics
+    #[serde(default)]
+    pub device: Option<String>,
+    /// Mount points for the filesystem capacity metrics, all local filesystems if empty
+    #[serde(default)]
+    pub mounts: Vec<String>,
+    /// Interface globs for the network metrics, `!` excludes, physical interfaces if empty
+    #[serde(default)]
+    pub interfaces: Vec<String>,
+}
@@ -1,3 +1,5 @@
+//! Persists the history between calls in a file guarded by a lock file
+
 use fs2::FileExt;
 use serde::Deserialize;
 use serde::Serialize;
@@ -6,22 +8,31 @@ use std::fs::File;
 use std::io::prelude::*;
 use std::io::BufReader;
 use std::io::Read;
-use std::path::PathBuf;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 use std::time::SystemTime;
 use std::time::SystemTimeError;
 use std::time::UNIX_EPOCH;
 
+/// Reading or writing the history failed
 #[derive(Debug)]
 pub enum StorageError {
+    /// Accessing `path` failed
     IO {
+        /// The underlying error
         source: std::io::Error,
+        /// The file or directory being accessed
         path: String,
     },
+    /// The history could not be encoded or decoded
     Serialization {
+        /// The underlying error
         source: serde_json::Error,
     },
+    /// The system clock is before the Unix epoch
     Time {
+        /// The underlying error
         source: SystemTimeError,
     },
 }
@@ -29,7 +40,7 @@ pub enum StorageError {
 impl std::fmt::Display for StorageError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         let s = match &self {
-            StorageError::IO { source, .. } => source.to_string(),
+            StorageError::IO { source, path } => format!("{}: {}", path, source),
             StorageError::Serialization { source } => source.to_string(),
             StorageError::Time { source } => source.to_string(),
         };
@@ -37,6 +48,8 @@ impl std::fmt::Display for StorageError {
     }
 }
 
+impl std::error::Error for StorageError {}
+
 impl From<std::time::SystemTimeError> for StorageError {
     fn from(e: std::time::SystemTimeError) -> StorageError {
         StorageError::Time { source: e }
@@ -51,6 +64,40 @@ struct TimeTagged<T> {
     payload: T,
 }
 
+/// Directory for the history and the daemon socket: `explicit` if given,
+/// `$XDG_RUNTIME_DIR/diffmetrik` or a per-user directory in the temp dir
+pub fn state_dir(explicit: Option<PathBuf>) -> PathBuf {
+    if let Some(dir) = explicit {
+        return dir;
+    }
+    match env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => PathBuf::from(dir).join("diffmetrik"),
+        None => env::temp_dir().join(format!("diffmetrik-{}", unsafe { libc::getuid() })),
+    }
+}
+
+/// Creates `dir` accessible by the current user only. An existing one must
+/// belong to the user and not be writable by others, the temp dir is shared.
+pub fn create_state_dir(dir: &Path) -> std::io::Result<()> {
+    std::fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)?;
+    let metadata = std::fs::metadata(dir)?;
+    let uid = unsafe { libc::getuid() };
+    if metadata.uid() != uid || metadata.permissions().mode() & 0o022 != 0 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            format!(
+                "{:?} must belong to uid {} and not be writable by others",
+                dir, uid
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// A JSON file holding the last value written along with when it was written
 #[derive(Debug)]
 pub struct Storage {
     path: PathBuf,
@@ -58,17 +105,34 @@ pub struct Storage {
 }
 
 impl Storage {
-    pub fn new(file_name: String, min_duration: Duration, debug: bool) -> Storage {
-        let mut p = env::temp_dir();
-        p.push(file_name);
-        let path = p;
-        if debug {
-            eprintln!("Storing data in: {:?}", &path);
+    /// Storage in `file_name` in `dir`, written at most every `min_duration`
+    pub fn new(dir: &Path, file_name: String, min_duration: Duration) -> Storage {
+        Storage {
+            path: dir.join(file_name),
+            min_duration,
+        }
+    }
+
+    /// The file the data is stored in
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn create_dir(&self) -> Result<()> {
+        match self.path.parent() {
+            Some(dir) => create_state_dir(dir).map_err(|e| StorageError::IO {
+                source: e,
+                path: format!("{:?}", dir),
+            }),
+            None => Ok(()),
         }
-        Storage { path, min_duration }
     }
 
+    /// Empties the file rather than removing it, the lock file stays in
+    /// place for processes waiting on it
     pub fn reset(&self) -> Result<()> {
+        self.create_dir()?;
+        let _lock = self.lock(true)?;
         File::create(&self.path)
             .map(|_| ())
             .map_err(|e| StorageError::IO {
@@ -77,21 +141,49 @@ impl Storage {
             })
     }
 
-    pub fn read<T>(&self) -> Result<T>
+    /// Writers hold this exclusively, readers shared. The data file itself is
+    /// replaced on every write, a lock on it would be lost with it.
+    fn lock(&self, exclusive: bool) -> Result<File> {
+        let path = self.sibling("lock");
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .map_err(|e| StorageError::IO {
+                source: e,
+                path: format!("{:?}", path),
+            })?;
+        let locked = if exclusive {
+            file.lock_exclusive()
+        } else {
+            file.lock_shared()
+        };
+        locked.map_err(|e| StorageError::IO {
+            source: e,
+            path: format!("{:?}", path),
+        })?;
+        Ok(file)
+    }
+
+    /// `<file>.<extension>` next to the data file
+    fn sibling(&self, extension: &str) -> PathBuf {
+        let mut name = self.path.file_name().unwrap_or_default().to_os_string();
+        name.push(".");
+        name.push(extension);
+        self.path.with_file_name(name)
+    }
+
+    /// Reads the data file, the caller proves it holds the lock
+    fn read_time_tagged<T>(&self, _lock: &File) -> Result<TimeTagged<T>>
     where
         for<'de> T: Deserialize<'de>,
-        T: std::fmt::Debug,
     {
         let path = &self.path;
         let file = File::open(path).map_err(|e| StorageError::IO {
             source: e,
             path: format!("{:?}", path),
         })?;
-        file.lock_shared().map_err(|e| StorageError::IO {
-            source: e,
-            path: format!("{:?}", path),
-        })?;
-
         let mut reader = BufReader::new(&file);
         let mut buf = String::new();
         reader
@@ -100,94 +192,68 @@ impl Storage {
                 source: e,
                 path: format!("{:?}", path),
             })?;
-
-        file.unlock().map_err(|e| StorageError::IO {
-            source: e,
-            path: format!("{:?}", path),
-        })?;
-
-        //dbg!(&buf);
         serde_json::from_str::<TimeTagged<T>>(&buf)
-            .map(|t| t.payload)
             .map_err(|e| StorageError::Serialization { source: e })
     }
 
+    /// The value last written
+    pub fn read<T>(&self) -> Result<T>
+    where
+        for<'de> T: Deserialize<'de>,
+        T: std::fmt::Debug,
+    {
+        self.create_dir()?;
+        let lock = self.lock(false)?;
+        self.read_time_tagged(&lock).map(|t| t.payload)
+    }
+
+    /// Replaces the stored data unless it was written less than `min_duration`
+    /// ago. The data goes to a temp file first and is renamed over the old
+    /// file, so an interrupted write leaves the previous data intact.
     pub fn write<T>(&self, data: &T) -> Result<()>
     where
         for<'de> T: Deserialize<'de>,
         T: Serialize,
         T: std::fmt::Debug,
     {
-        // Open
-        let path = &self.path;
-        let mut file = std::fs::OpenOptions::new()
-            .create(true)
-            .read(true)
-            .write(true)
-            .open(&self.path)
-            .map_err(|e| StorageError::IO {
-                source: e,
-                path: format!("{:?}", path),
-            })?;
-
-        // Lock
-        file.lock_exclusive().map_err(|e| StorageError::IO {
-            source: e,
-            path: format!("{:?}", path),
-        })?;
-        // Read current value
-        file.rewind().map_err(|e| StorageError::IO {
-            source: e,
-            path: format!("{:?}", path),
-        })?;
+        self.create_dir()?;
+        let lock = self.lock(true)?;
 
-        let mut reader = BufReader::new(&file);
-        let mut buf = String::new();
-        reader
-            .read_to_string(&mut buf)
-            .map_err(|e| StorageError::IO {
-                source: e,
-                path: format!("{:?}", path),
-            })?;
-
-        // Get old timestamp
-        let timetagged: Result<TimeTagged<T>, serde_json::error::Error> =
-            serde_json::from_str(&buf);
+        // Get old timestamp, an unreadable file is replaced
+        let timetagged: Result<TimeTagged<T>> = self.read_time_tagged(&lock);
         let now: Duration = SystemTime::now().duration_since(UNIX_EPOCH)?;
-
         let should_write = timetagged
-            .map(|t| (now - t.time) >= self.min_duration)
+            // A clock that went backwards must not leave the old sample in place forever
+            .map(|t| {
+                now.checked_sub(t.time)
+                    .is_none_or(|d| d >= self.min_duration)
+            })
             .unwrap_or(true);
-        if should_write {
-            file.rewind().map_err(|e| StorageError::IO {
-                source: e,
-                path: format!("{:?}", path),
-            })?;
-
-            let timetagged = TimeTagged {
-                time: SystemTime::now().duration_since(UNIX_EPOCH)?,
-                payload: data,
-            };
-            let serialized = serde_json::to_string(&timetagged).unwrap();
-            file.set_len(serialized.len() as u64)
-                .map_err(|e| StorageError::IO {
-                    source: e,
-                    path: format!("{:?}", path),
-                })?;
-
-            file.write_all(serialized.as_bytes())
-                .map_err(|e| StorageError::IO {
-                    source: e,
-                    path: format!("{:?}", path),
-                })?;
+        if !should_write {
+            return Ok(());
         }
-        // Unlock
-        file.unlock().map_err(|e| StorageError::IO {
+
+        let timetagged = TimeTagged {
+            time: now,
+            payload: data,
+        };
+        let serialized = serde_json::to_string(&timetagged)
+            .map_err(|e| StorageError::Serialization { source: e })?;
+        let temp = self.sibling("tmp");
+        let mut file = File::create(&temp).map_err(|e| StorageError::IO {
             source: e,
-            path: format!("{:?}", path),
+            path: format!("{:?}", temp),
         })?;
-
-        Ok(())
+        file.write_all(serialized.as_bytes())
+            .and_then(|_| file.sync_all())
+            .map_err(|e| StorageError::IO {
+                source: e,
+                path: format!("{:?}", temp),
+            })?;
+        std::fs::rename(&temp, &self.path).map_err(|e| StorageError::IO {
+            source: e,
+            path: format!("{:?}", self.path),
+        })
     }
 }
 
@@ -203,24 +269,23 @@ mod tests {
     #[test]
     fn path_set_correctly() {
         let path = "diffmetrik_test_path.json".to_string();
-        let s = Storage::new(path.to_string(), Duration::new(0, 0), true);
+        let s = Storage::new(&test_dir(), path.to_string(), Duration::new(0, 0));
         let full_path = s.path;
-        assert_eq!(full_path.ends_with(path), true);
+        assert!(full_path.ends_with(path));
     }
 
     #[test]
     fn file_reset() {
         let path = "diffmetrik_test_reset.json".to_string();
         remove_file(&path);
-        let s = Storage::new(path.to_string(), Duration::new(0, 0), true);
+        let s = Storage::new(&test_dir(), path.to_string(), Duration::new(0, 0));
         let full_path = &s.path;
         assert!(
             !full_path.exists(),
             "the file should not exist at the beginning of test"
         );
-        match s.reset() {
-            Ok(_) => assert!(true, "file reset successfully"),
-            Err(e) => assert!(false, "error resetting file: {}", e),
+        if let Err(e) = s.reset() {
+            panic!("error resetting file: {}", e);
         }
         let metadata = std::fs::metadata(full_path).expect("should get metadata");
         assert!(metadata.is_file(), "should be a file");
@@ -231,7 +296,7 @@ mod tests {
     fn file_write() {
         let path = "diffmetrik_test_write.json".to_string();
         remove_file(&path);
-        let s = Storage::new(path.to_string(), Duration::new(0, 0), true);
+        let s = Storage::new(&test_dir(), path.to_string(), Duration::new(0, 0));
         let full_path = &s.path;
         assert!(
             !full_path.exists(),
@@ -240,10 +305,8 @@ mod tests {
         let w = TestStruct {
             test_string: "something".to_string(),
         };
-        let res = s.write(&w);
-        match res {
-            Ok(_) => assert!(true),
-            Err(e) => assert!(false, "writing failed with {}", e),
+        if let Err(e) = s.write(&w) {
+            panic!("writing failed with {}", e);
         }
         assert!(
             full_path.exists(),
@@ -255,19 +318,19 @@ mod tests {
     fn file_write_len() {
         let path = "diffmetrik_test_write_len.json".to_string();
         remove_file(&path);
-        let s = Storage::new(path.to_string(), Duration::new(0, 0), true);
+        let s = Storage::new(&test_dir(), path.to_string(), Duration::new(0, 0));
         let full_path = &s.path;
         let w1 = TestStruct {
             test_string: "something".to_string(),
         };
         s.write(&w1).expect("written w1");
-        let meta1 = std::fs::metadata(&full_path).expect("metadata");
+        let meta1 = std::fs::metadata(full_path).expect("metadata");
         let w2 = TestStruct {
             test_string: "som".to_string(),
         };
         let len1 = meta1.len();
         s.write(&w2).expect("written w2");
-        let meta2 = std::fs::metadata(&full_path).expect("metadata");
+        let meta2 = std::fs::metadata(full_path).expect("metadata");
         let len2 = meta2.len();
         assert!(
             dbg!(len1 > len2, len1, len2).0,
@@ -278,7 +341,7 @@ mod tests {
     #[test]
     fn file_read() {
         let path = "diffmetrik_test_read.json".to_string();
-        let s = Storage::new(path.to_string(), Duration::new(0, 0), true);
+        let s = Storage::new(&test_dir(), path.to_string(), Duration::new(0, 0));
         let payload = "something".to_string();
         let w = TestStruct {
             test_string: payload.clone(),
@@ -290,12 +353,96 @@ mod tests {
                 res.test_string == payload,
                 "payload after read was different from payload written"
             ),
-            Err(e) => assert!(false, "error reading: {}", e),
+            Err(e) => panic!("error reading: {}", e),
         }
     }
 
-    fn remove_file(path: &String) {
-        let s = Storage::new(path.clone(), Duration::new(0, 0), true);
+    #[test]
+    fn file_truncated() {
+        let path = "diffmetrik_test_truncated.json".to_string();
+        let s = Storage::new(&test_dir(), path, Duration::new(0, 0));
+        let w = TestStruct {
+            test_string: "something".to_string(),
+        };
+        s.write(&w).expect("file written");
+        let written = std::fs::read(&s.path).expect("file read");
+        std::fs::write(&s.path, &written[..written.len() / 2]).expect("file truncated");
+        let res: Result<TestStruct, _> = s.read();
+        assert!(
+            matches!(res, Err(StorageError::Serialization { .. })),
+            "truncated file should not parse"
+        );
+        s.write(&w).expect("truncated file replaced");
+        let res: TestStruct = s.read().expect("file read after rewrite");
+        assert_eq!(res.test_string, w.test_string);
+    }
+
+    #[test]
+    fn file_corrupted() {
+        let path = "diffmetrik_test_corrupted.json".to_string();
+        let s = Storage::new(&test_dir(), path, Duration::new(0, 0));
+        s.create_dir().expect("directory created");
+        std::fs::write(&s.path, b"\0\0{\"time\"").expect("file corrupted");
+        let res: Result<TestStruct, _> = s.read();
+        assert!(res.is_err(), "corrupted file should not parse");
+        let w = TestStruct {
+            test_string: "something".to_string(),
+        };
+        s.write(&w).expect("corrupted file replaced");
+        let res: TestStruct = s.read().expect("file read after rewrite");
+        assert_eq!(res.test_string, w.test_string);
+    }
+
+    #[test]
+    fn interrupted_write() {
+        let path = "diffmetrik_test_interrupted.json".to_string();
+        let s = Storage::new(&test_dir(), path, Duration::new(0, 0));
+        let w = TestStruct {
+            test_string: "something".to_string(),
+        };
+        s.write(&w).expect("file written");
+        // Killed while writing the temp file, before the rename
+        std::fs::write(s.sibling("tmp"), b"{\"time\":{\"se").expect("partial temp file");
+        let res: TestStruct = s.read().expect("previous data intact");
+        assert_eq!(res.test_string, w.test_string);
+        let w2 = TestStruct {
+            test_string: "else".to_string(),
+        };
+        s.write(&w2).expect("leftover temp file overwritten");
+        let res: TestStruct = s.read().expect("file read");
+        assert_eq!(res.test_string, w2.test_string);
+    }
+
+    #[test]
+    fn state_dir_permissions() {
+        let dir = env::temp_dir().join(format!("diffmetrik-test-state-{}", std::process::id()));
+        let s = Storage::new(&dir, "state.json".to_string(), Duration::new(0, 0));
+        s.reset().expect("directory created");
+        let mode = std::fs::metadata(&dir)
+            .expect("metadata")
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o700);
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o777)).unwrap();
+        assert!(
+            s.reset().is_err(),
+            "should refuse a world writable directory"
+        );
+        std::fs::remove_dir_all(&dir).expect("removed test directory");
+        assert_eq!(
+            state_dir(Some(dir.clone())),
+            dir,
+            "an explicit directory takes precedence"
+        );
+    }
+
+    /// The shared temp dir itself is writable by everyone
+    fn test_dir() -> PathBuf {
+        env::temp_dir().join(format!("diffmetrik-tests-{}", unsafe { libc::getuid() }))
+    }
+
+    fn remove_file(path: &str) {
+        let s = Storage::new(&test_dir(), path.to_string(), Duration::new(0, 0));
         let full_path = s.path;
         if full_path.exists() {
             std::fs::remove_file(full_path).expect("removed temp file");